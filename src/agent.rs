@@ -1,51 +1,480 @@
-use std::{sync::{Arc, Mutex}, f64::consts::PI};
+use std::{sync::{Arc, Mutex}, collections::VecDeque, fmt};
 use rand::{distributions::Uniform, prelude::Distribution};
 
 use cairo::Context;
 
-use crate::{node::Node, lane::Lane};
+use crate::{node::Node, lane::{Lane, LaneKind}, connection::SignKind, transit::Stop};
+
+// Ticks an agent has to stand still at a stop sign
+const STOP_TICKS: u32 = 50;
+// Space kept behind the agent ahead
+pub const MIN_GAP: f64 = 1.5;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AgentKind {
+    Car,
+    Truck,
+    Bus,
+    Tram,
+    Pedestrian,
+    Bike,
+}
+
+pub struct AgentKindInfo {
+    pub name: &'static str,
+    pub length: f64,
+    pub width: f64,
+    // Fastest we go, whatever the speed limit says
+    pub top_speed: f64,
+    // Speed gained per tick
+    pub acceleration: f64,
+    pub color: (f64, f64, f64),
+}
+
+// Indexed by AgentKind
+const AGENT_KIND_INFO: [AgentKindInfo; 6] = [
+    AgentKindInfo { name: "Car", length: 4.5, width: 2.0, top_speed: f64::MAX, acceleration: 0.05, color: (0.42, 0.45, 0.83) },
+    AgentKindInfo { name: "Truck", length: 10.0, width: 2.5, top_speed: 2.0, acceleration: 0.02, color: (0.75, 0.45, 0.25) },
+    AgentKindInfo { name: "Bus", length: 12.0, width: 2.5, top_speed: 2.0, acceleration: 0.03, color: (0.42, 0.45, 0.83) },
+    AgentKindInfo { name: "Tram", length: 20.0, width: 2.6, top_speed: 2.0, acceleration: 0.03, color: (0.42, 0.45, 0.83) },
+    AgentKindInfo { name: "Pedestrian", length: 0.6, width: 0.6, top_speed: 0.15, acceleration: 0.05, color: (0.85, 0.75, 0.35) },
+    AgentKindInfo { name: "Bike", length: 1.8, width: 0.6, top_speed: 0.5, acceleration: 0.04, color: (0.20, 0.65, 0.35) },
+];
+
+impl AgentKind {
+    pub const ALL: [AgentKind; 6] = [
+        AgentKind::Car,
+        AgentKind::Truck,
+        AgentKind::Bus,
+        AgentKind::Tram,
+        AgentKind::Pedestrian,
+        AgentKind::Bike,
+    ];
+
+    pub fn info(&self) -> &'static AgentKindInfo {
+        &AGENT_KIND_INFO[*self as usize]
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.info().name
+    }
+
+    pub fn length(&self) -> f64 {
+        self.info().length
+    }
+
+    pub fn width(&self) -> f64 {
+        self.info().width
+    }
+
+    pub fn top_speed(&self) -> f64 {
+        self.info().top_speed
+    }
+
+    pub fn acceleration(&self) -> f64 {
+        self.info().acceleration
+    }
+
+    pub fn color(&self) -> (f64, f64, f64) {
+        self.info().color
+    }
+
+    pub fn can_use(&self, lane_kind: LaneKind) -> bool {
+        match self {
+            AgentKind::Car | AgentKind::Truck => lane_kind == LaneKind::Car,
+            AgentKind::Bus => lane_kind == LaneKind::Bus || lane_kind == LaneKind::Car,
+            AgentKind::Tram => lane_kind == LaneKind::Tram,
+            AgentKind::Pedestrian => lane_kind == LaneKind::Pedestrian,
+            AgentKind::Bike => lane_kind == LaneKind::Bike || lane_kind == LaneKind::Car,
+        }
+    }
+
+    // The lanes we take whenever there is a choice
+    pub fn home_lane(&self) -> LaneKind {
+        match self {
+            AgentKind::Car | AgentKind::Truck => LaneKind::Car,
+            AgentKind::Bus => LaneKind::Bus,
+            AgentKind::Tram => LaneKind::Tram,
+            AgentKind::Pedestrian => LaneKind::Pedestrian,
+            AgentKind::Bike => LaneKind::Bike,
+        }
+    }
+
+    // Not every cyclist is in the same hurry
+    pub fn sample_top_speed(&self) -> f64 {
+        match self {
+            AgentKind::Bike => {
+                let mut rng = rand::thread_rng();
+                self.top_speed() * Uniform::from(0.6..1.2).sample(&mut rng)
+            }
+            _ => self.top_speed(),
+        }
+    }
+}
+
+// What became of an agent after a tick
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Step {
+    Moving,
+    // At its destination
+    Arrived,
+    // Left the network at a sink
+    Sunk,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StepError {
+    // Nowhere to go at the end of the lane, at the given position
+    DeadEnd(f64, f64),
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepError::DeadEnd(x, y) => write!(f, "dead end at {:.1} {:.1}", x, y),
+        }
+    }
+}
 
 pub struct Agent {
+    pub id: usize,
+    pub kind: AgentKind,
     pub c: Node,
+    pub heading: f64,
     pub l: Arc<Mutex<Lane>>,
     pub distance: f64,
+    pub speed: f64,
+    pub top_speed: f64,
+    pub stopped_ticks: u32,
+    // Lanes to take next, wander when empty
+    pub route: VecDeque<Arc<Mutex<Lane>>>,
+    // Transit stops still to serve
+    pub stops: VecDeque<Stop>,
+    pub dwell: u32,
+    pub dwelling: u32,
+    pub color: (f64, f64, f64),
+    // Where the trip ends, as (lane, distance)
+    pub destination: Option<(Arc<Mutex<Lane>>, f64)>,
+    pub departure: u64,
+    // Distance covered so far
+    pub travelled: f64,
+    // Ticks the distance covered would take on empty roads
+    pub free_flow_time: f64,
 }
 
 impl Agent {
 
-    pub fn new(l: Arc<Mutex<Lane>>, distance: f64) -> Self {
-        let c = l.lock().unwrap().position_at(distance);
-        Self { c, l, distance }
+    pub fn new(id: usize, kind: AgentKind, l: Arc<Mutex<Lane>>, distance: f64) -> Self {
+        let mut l_lock = l.lock().unwrap();
+        let c = l_lock.position_at(distance);
+        let heading = l_lock.heading_at(distance);
+        l_lock.enter(id, distance, kind.length());
+        drop(l_lock);
+        Self {
+            id,
+            kind,
+            c,
+            heading,
+            l,
+            distance,
+            speed: 0.0,
+            top_speed: kind.sample_top_speed(),
+            stopped_ticks: 0,
+            route: VecDeque::new(),
+            stops: VecDeque::new(),
+            dwell: 0,
+            dwelling: 0,
+            color: kind.color(),
+            destination: None,
+            departure: 0,
+            travelled: 0.0,
+            free_flow_time: 0.0,
+        }
+    }
+
+    // Move d along the current lane.
+    fn advance(&mut self, d: f64) {
+        let free_speed = self.l.lock().unwrap().max_speed().min(self.top_speed);
+        self.distance += d;
+        self.travelled += d;
+        self.free_flow_time += d / free_speed.max(0.01);
+    }
+
+    fn leave_network(&mut self) {
+        self.l.lock().unwrap().leave(self.id);
     }
 
-    pub fn update(& mut self) {
-        let mut distance_to_move = 2.0;
-        let mut moved_distance = 0.0;
-        while distance_to_move > moved_distance {
+    pub fn update(& mut self, tick: u64) -> Result<Step, StepError> {
+        // Passengers getting on and off
+        if self.dwelling > 0 {
+            self.dwelling -= 1;
+            return Ok(Step::Moving);
+        }
+
+        // Speed up to the speed limit, brake right away when above it
+        let max_speed = self.l.lock().unwrap().max_speed().min(self.top_speed);
+        self.speed = (self.speed + self.kind.acceleration()).min(max_speed);
+        let mut distance_to_move = self.speed;
+
+        // Keep our distance to the agent ahead, on this lane or the next
+        let leader = self.l.lock().unwrap().leader_distance(self.id, self.distance);
+        let leader = leader.or_else(|| self.next_leader_distance());
+        if let Some(leader) = leader {
+            let front = self.distance + self.kind.length() / 2.0;
+            distance_to_move = distance_to_move.min((leader - front - MIN_GAP).max(0.0));
+            self.speed = distance_to_move;
+        }
+
+        while distance_to_move > 0.0 {
+            // Serve the next stop when passing it
+            if let Some(stop) = self.stops.front() {
+                if Arc::ptr_eq(&stop.lane, &self.l)
+                    && stop.distance >= self.distance
+                    && stop.distance <= self.distance + distance_to_move
+                {
+                    self.advance(stop.distance - self.distance);
+                    self.stops.pop_front();
+                    self.dwelling = self.dwell;
+                    self.speed = 0.0;
+                    break;
+                }
+            }
+
+            // Are we there yet?
+            if let Some((lane, d)) = &self.destination {
+                if self.route.is_empty()
+                    && Arc::ptr_eq(lane, &self.l)
+                    && *d >= self.distance
+                    && *d <= self.distance + distance_to_move
+                {
+                    let d = *d;
+                    self.advance(d - self.distance);
+                    self.leave_network();
+                    return Ok(Step::Arrived);
+                }
+            }
+
             let lane_length = self.l.lock().unwrap().length();
             let remaining_distance = lane_length - self.distance;
-            if remaining_distance < distance_to_move - moved_distance {
-                moved_distance += remaining_distance;
-                distance_to_move -= remaining_distance;
-                let mut new_lane_number = self.l.lock().unwrap().c1.lock().unwrap().out_lane.len();
-                let mut rng = rand::thread_rng();
-                new_lane_number = Uniform::from(0..new_lane_number).sample(&mut rng);
-                let new_lane = self.l.lock().unwrap().c1.lock().unwrap().out_lane[new_lane_number].clone();
-                self.distance = 0.0;
-                self.l = new_lane;
+            if remaining_distance >= distance_to_move {
+                self.advance(distance_to_move);
+                break;
+            }
+
+            // Sinks take agents off the map
+            if self.l.lock().unwrap().c1.lock().unwrap().sink {
+                self.advance(remaining_distance);
+                self.leave_network();
+                return Ok(Step::Sunk);
+            }
+
+            // Dead ends take agents off the map too, but it is an error
+            let choices = self.choices();
+            if choices.is_empty() {
+                self.advance(remaining_distance);
+                self.leave_network();
+                let c = self.l.lock().unwrap().position_at(lane_length);
+                return Err(StepError::DeadEnd(c.x, c.y));
+            }
+
+            // We reached the end of the lane, is there room on the next one
+            // and are we allowed to leave?
+            let new_lane = self.next_lane(&choices);
+            let room = new_lane.lock().unwrap().has_room(0.0, self.kind.length());
+            if !room || !self.may_leave(tick) {
+                self.advance(remaining_distance);
+                self.speed = 0.0;
+                break;
+            }
+            self.advance(remaining_distance);
+            distance_to_move -= remaining_distance;
+
+            if self.route.pop_front().map_or(false, |next| !Arc::ptr_eq(&next, &new_lane)) {
+                // We lost our way
+                self.route.clear();
+            }
+            self.l.lock().unwrap().leave(self.id);
+            new_lane.lock().unwrap().enter(self.id, 0.0, self.kind.length());
+            self.distance = 0.0;
+            self.l = new_lane;
+        }
+
+        let mut l_lock = self.l.lock().unwrap();
+        l_lock.move_occupant(self.id, self.distance);
+        self.c = l_lock.position_at(self.distance);
+        self.heading = l_lock.heading_at(self.distance);
+        Ok(Step::Moving)
+    }
+
+    // Lanes we could take at the end of the current lane, our own kind of
+    // lanes when there are any.
+    fn choices(&self) -> Vec<Arc<Mutex<Lane>>> {
+        let c1 = self.l.lock().unwrap().c1.clone();
+        let out_lanes = c1.lock().unwrap().out_lane.clone();
+
+        let allowed: Vec<Arc<Mutex<Lane>>> = out_lanes
+            .into_iter()
+            .filter(|l| self.kind.can_use(l.lock().unwrap().kind))
+            .collect();
+        let home: Vec<Arc<Mutex<Lane>>> = allowed
+            .iter()
+            .filter(|l| l.lock().unwrap().kind == self.kind.home_lane())
+            .cloned()
+            .collect();
+        if home.is_empty() {
+            allowed
+        } else {
+            home
+        }
+    }
+
+    // The next lane on the route, if it goes on from this lane
+    fn route_lane(&self) -> Option<Arc<Mutex<Lane>>> {
+        let c1 = self.l.lock().unwrap().c1.clone();
+        let out_lanes = c1.lock().unwrap().out_lane.clone();
+        self.route
+            .front()
+            .filter(|next| out_lanes.iter().any(|l| Arc::ptr_eq(l, next)))
+            .cloned()
+    }
+
+    // Follow the route, or pick any of the choices.
+    fn next_lane(&self, choices: &[Arc<Mutex<Lane>>]) -> Arc<Mutex<Lane>> {
+        if let Some(next) = self.route_lane() {
+            return next;
+        }
+
+        let mut rng = rand::thread_rng();
+        let new_lane_number = Uniform::from(0..choices.len()).sample(&mut rng);
+        choices[new_lane_number].clone()
+    }
+
+    // Distance from the start of this lane to the rear of the closest agent
+    // on the lanes we may take next. Without a route any of them will do.
+    fn next_leader_distance(&self) -> Option<f64> {
+        let next_lanes = match self.route_lane() {
+            Some(next) => vec![next],
+            None => self.choices(),
+        };
+        let length = self.l.lock().unwrap().length();
+        next_lanes
+            .iter()
+            .filter_map(|l| l.lock().unwrap().leader_distance(self.id, f64::NEG_INFINITY))
+            .map(|d| length + d)
+            .fold(None, |min: Option<f64>, d| Some(min.map_or(d, |m| m.min(d))))
+    }
+
+    // Signs at the end of the lane decide if we can continue.
+    fn may_leave(&mut self, tick: u64) -> bool {
+        let c1 = self.l.lock().unwrap().c1.clone();
+        let mut c1_lock = c1.lock().unwrap();
+        // Pedestrians on the crosswalk
+        if c1_lock.blocked {
+            return false;
+        }
+        if c1_lock.sign == SignKind::None {
+            return true;
+        }
+
+        if c1_lock.cleared == Some(self.id) {
+            c1_lock.cleared = None;
+            c1_lock.waiting.retain(|(id, _)| *id != self.id);
+            self.stopped_ticks = 0;
+            return true;
+        }
+
+        if !c1_lock.is_waiting(self.id) {
+            if c1_lock.sign == SignKind::Stop && self.stopped_ticks < STOP_TICKS {
+                self.stopped_ticks += 1;
             } else {
-                moved_distance += distance_to_move;
-                distance_to_move -= moved_distance;
+                c1_lock.waiting.push((self.id, tick));
             }
         }
-        self.distance += moved_distance;
-        let new_position = self.l.lock().unwrap().position_at(self.distance);
-        self.c = new_position;
+        false
     }
 
     pub fn draw(&self, context: &Context ) {
-        context.set_source_rgb(0.42, 0.45, 0.83);
-        context.arc(self.c.x, self.c.y, 2.5, 0.0, PI * 2.0);
-        context.fill().expect("Woops! Draw failed!");
+        draw_agent(context, self.kind, &self.c, self.heading, self.color);
     }
-}
\ No newline at end of file
+}
+
+// Also draws agents from a recording, which only know where they were.
+pub fn draw_agent(context: &Context, kind: AgentKind, c: &Node, heading: f64, color: (f64, f64, f64)) {
+    let (r, g, b) = color;
+    let (length, width) = (kind.length(), kind.width());
+    context.save().expect("Woops! Draw failed!");
+    context.translate(c.x, c.y);
+    context.rotate(heading);
+    context.rectangle(-length / 2.0, -width / 2.0, length, width);
+    context.set_source_rgb(r, g, b);
+    context.fill().expect("Woops! Draw failed!");
+    context.restore().expect("Woops! Draw failed!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{intersection::Intersection, road::{Road, RoadKind}, road_profile::RoadProfile};
+
+    #[test]
+    fn test_dead_end() {
+        let i0 = Arc::new(Mutex::new(Intersection::new(0.0, 0.0)));
+        let i1 = Arc::new(Mutex::new(Intersection::new(50.0, 0.0)));
+        let i2 = Arc::new(Mutex::new(Intersection::new(100.0, 0.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("One way"),
+            right_lane_kinds: vec![LaneKind::Car],
+            left_lane_kinds: vec![],
+        }));
+        let road = Road::new(i0, i1, i2, road_profile, RoadKind::Residential);
+        let lane = road.lanes[0].clone();
+
+        // Nowhere to go at the end of a one way road
+        let mut agent = Agent::new(0, AgentKind::Car, lane.clone(), 0.0);
+        let mut result = Ok(Step::Moving);
+        for tick in 0..1000 {
+            result = agent.update(tick);
+            if result != Ok(Step::Moving) {
+                break;
+            }
+        }
+        assert!(matches!(result, Err(StepError::DeadEnd(_, _))));
+        assert!(!lane.lock().unwrap().is_occupied());
+    }
+
+    #[test]
+    fn test_wait_for_room() {
+        let i0 = Arc::new(Mutex::new(Intersection::new(0.0, 0.0)));
+        let i2 = Arc::new(Mutex::new(Intersection::new(100.0, 0.0)));
+        let i4 = Arc::new(Mutex::new(Intersection::new(200.0, 0.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("One way"),
+            right_lane_kinds: vec![LaneKind::Car],
+            left_lane_kinds: vec![],
+        }));
+        let i1 = Arc::new(Mutex::new(Intersection::new(50.0, 0.0)));
+        let road = Road::new(i0, i1, i2.clone(), road_profile.clone(), RoadKind::Residential);
+        let i3 = Arc::new(Mutex::new(Intersection::new(150.0, 0.0)));
+        Road::new(i2.clone(), i3, i4, road_profile, RoadKind::Residential);
+        let lane = road.lanes[0].clone();
+        let length = lane.lock().unwrap().length();
+
+        // Someone stands at the start of the lane through the intersection
+        let next = i2.lock().unwrap().lanes[0].clone();
+        next.lock().unwrap().enter(99, 0.0, AgentKind::Car.length());
+
+        let mut agent = Agent::new(0, AgentKind::Car, lane.clone(), length - 30.0);
+        for tick in 0..100 {
+            assert_eq!(agent.update(tick), Ok(Step::Moving));
+        }
+        assert!(Arc::ptr_eq(&agent.l, &lane));
+        assert!(agent.distance + AgentKind::Car.length() < length);
+
+        // And goes on once the way is clear
+        next.lock().unwrap().leave(99);
+        for tick in 100..200 {
+            agent.update(tick).unwrap();
+        }
+        assert!(!Arc::ptr_eq(&agent.l, &lane));
+    }
+}
@@ -1,11 +1,66 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    f64::consts::PI,
+    sync::{Arc, Mutex},
+};
+
+use cairo::Context;
 
 use crate::{lane::{Lane, LaneKind}, node::Node};
 
-#[derive(PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum ConnectionKind {
     In,
     Out,
+    Ring,
+}
+
+impl ConnectionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConnectionKind::In => "In",
+            ConnectionKind::Out => "Out",
+            ConnectionKind::Ring => "Ring",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ConnectionKind> {
+        [ConnectionKind::In, ConnectionKind::Out, ConnectionKind::Ring]
+            .iter()
+            .copied()
+            .find(|k| k.name() == name)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SignKind {
+    None,
+    Stop,
+    Yield,
+}
+
+impl SignKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignKind::None => "None",
+            SignKind::Stop => "Stop",
+            SignKind::Yield => "Yield",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SignKind> {
+        [SignKind::None, SignKind::Stop, SignKind::Yield]
+            .iter()
+            .copied()
+            .find(|k| k.name() == name)
+    }
+
+    pub fn next(&self) -> SignKind {
+        match self {
+            SignKind::None => SignKind::Stop,
+            SignKind::Stop => SignKind::Yield,
+            SignKind::Yield => SignKind::None,
+        }
+    }
 }
 
 pub struct Connection {
@@ -16,6 +71,15 @@ pub struct Connection {
     pub offset: f64,
     pub in_lane: Vec<Arc<Mutex<Lane>>>,
     pub out_lane: Vec<Arc<Mutex<Lane>>>,
+    pub sign: SignKind,
+    // Agents waiting at the sign as (agent id, arrival tick)
+    pub waiting: Vec<(usize, u64)>,
+    // Agent allowed to pass the sign next
+    pub cleared: Option<usize>,
+    // Pedestrians are crossing in front of the connection
+    pub blocked: bool,
+    // Agents leave the map here
+    pub sink: bool,
 }
 
 impl Connection {
@@ -28,6 +92,60 @@ impl Connection {
             offset,
             in_lane: Vec::new(),
             out_lane: Vec::new(),
+            sign: SignKind::None,
+            waiting: Vec::new(),
+            cleared: None,
+            blocked: false,
+            sink: false,
+        }
+    }
+
+    pub fn is_waiting(&self, id: usize) -> bool {
+        self.waiting.iter().any(|(w, _)| *w == id)
+    }
+
+    // Stop waiting for an agent that is gone.
+    pub fn forget(&mut self, id: usize) {
+        self.waiting.retain(|(w, _)| *w != id);
+        if self.cleared == Some(id) {
+            self.cleared = None;
+        }
+    }
+
+    pub fn draw(&self, context: &Context) {
+        if self.sink {
+            context.set_source_rgb(0.15, 0.15, 0.15);
+            context.rectangle(self.center.x - 1.0, self.center.y - 1.0, 2.0, 2.0);
+            context.fill().expect("Failed to draw sink!");
+        }
+
+        match self.sign {
+            SignKind::None => {}
+            SignKind::Stop => {
+                // Octagon
+                context.set_source_rgb(0.80, 0.10, 0.10);
+                for i in 0..8 {
+                    let n = self.center.offset(PI / 8.0 + PI / 4.0 * i as f64, 1.5);
+                    context.line_to(n.x, n.y);
+                }
+                context.close_path();
+                context.fill().expect("Failed to draw stop sign!");
+            }
+            SignKind::Yield => {
+                // Triangle pointing into the intersection
+                let a = self.angle + PI;
+                let n0 = self.center.offset(a, 1.5);
+                let n1 = self.center.offset(a + PI * 2.0 / 3.0, 1.5);
+                let n2 = self.center.offset(a - PI * 2.0 / 3.0, 1.5);
+                context.move_to(n0.x, n0.y);
+                context.line_to(n1.x, n1.y);
+                context.line_to(n2.x, n2.y);
+                context.close_path();
+                context.set_source_rgb(0.95, 0.95, 0.95);
+                context.fill_preserve().expect("Failed to draw yield sign!");
+                context.set_source_rgb(0.80, 0.10, 0.10);
+                context.stroke().expect("Failed to draw yield sign!");
+            }
         }
     }
 }
@@ -4,6 +4,9 @@ use cairo::Context;
 
 use crate::node::Node;
 
+// Distance between the points curves are sampled at when exported
+pub const SAMPLE_STEP: f64 = 2.0;
+
 pub struct Curve {
     pub n0: Node,
     pub n1: Node,
@@ -89,6 +92,25 @@ impl Curve {
         }
     }
 
+    // Arc leaving n0 in direction a0 and ending in n1.
+    pub fn new_tangent(n0: Node, a0: f64, n1: Node) -> Self {
+        let p = n0.offset(a0 + PI / 2.0, 1.0);
+        let (px, py) = (p.x - n0.x, p.y - n0.y);
+        let (dx, dy) = (n1.x - n0.x, n1.y - n0.y);
+        let dot = dx * px + dy * py;
+        if dot.abs() < 0.001 {
+            // n1 is straight ahead
+            let c = n0.offset(n0.angle(&n1), n0.distance(&n1) / 2.0);
+            return Curve::new_1(n0, n1, c, false, false);
+        }
+        let t = (dx * dx + dy * dy) / (2.0 * dot);
+        let c = Node::new(n0.x + t * px, n0.y + t * py);
+
+        // Going towards increasing angles around c?
+        let cross = (n0.x - c.x) * a0.sin() - (n0.y - c.y) * a0.cos();
+        Curve::new_1(n0, n1, c, true, cross < 0.0)
+    }
+
     pub fn reverse(&self) -> Curve {
         Curve::new_1(self.n1, self.n0, self.c, self.is_curved, !self.is_reversed)
     }
@@ -239,6 +261,23 @@ mod tests {
         assert_eq!((length * 100.0).round(), 3142.0);
     }
 
+    #[test]
+    fn test_new_tangent() {
+        let n0 = Node::new(10.0, 10.0);
+        let n1 = Node::new(20.0, 20.0);
+        let c0 = Curve::new_tangent(n0, 0.0, n1);
+        assert_eq!(c0.c.x.round(), 10.0);
+        assert_eq!(c0.c.y.round(), 20.0);
+        assert!(!c0.is_reversed);
+        assert_eq!((c0.length() * 100.0).round(), 1571.0);
+
+        let n2 = Node::new(20.0, 0.0);
+        let c1 = Curve::new_tangent(n0, 0.0, n2);
+        assert!(c1.is_reversed);
+        let end = c1.position_at(c1.length());
+        assert!(end.distance(&n2) < 0.01);
+    }
+
     fn make_line() -> Curve {
         let n0 = Node::new(10.0, 10.0);
         let n1 = Node::new(30.0, 10.0);
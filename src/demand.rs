@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::lane::Lane;
+
+// Ticks in a simulated hour
+pub const TICKS_PER_HOUR: u64 = 3600;
+
+// Share of the daily trips to work starting in each hour
+const TO_WORK: [f64; 24] = [
+    0.00, 0.00, 0.00, 0.00, 0.00, 0.02, 0.08, 0.25, 0.25, 0.10, 0.05, 0.04,
+    0.04, 0.04, 0.03, 0.03, 0.02, 0.02, 0.01, 0.01, 0.01, 0.00, 0.00, 0.00,
+];
+// Share of the daily trips back home starting in each hour
+const TO_HOME: [f64; 24] = [
+    0.00, 0.00, 0.00, 0.00, 0.00, 0.00, 0.00, 0.01, 0.01, 0.02, 0.03, 0.04,
+    0.05, 0.04, 0.04, 0.06, 0.15, 0.22, 0.15, 0.07, 0.05, 0.03, 0.02, 0.01,
+];
+
+// Where a trip starts or ends, as (lane, distance)
+pub type Access = (Arc<Mutex<Lane>>, f64);
+
+pub struct Demand {
+    pub enabled: bool,
+    // Round trips per residential plot and day
+    pub trips_per_day: f64,
+}
+
+impl Demand {
+    pub fn new() -> Self {
+        Self { enabled: true, trips_per_day: 2.0 }
+    }
+
+    pub fn hour(tick: u64) -> usize {
+        (tick / TICKS_PER_HOUR % 24) as usize
+    }
+
+    // Trips per home starting in the hour, to work and back home.
+    pub fn per_hour(&self, hour: usize) -> (f64, f64) {
+        (self.trips_per_day * TO_WORK[hour], self.trips_per_day * TO_HOME[hour])
+    }
+
+    // Trips starting this tick, from homes to work places and back.
+    pub fn trips(&self, tick: u64, homes: &[Access], work: &[Access]) -> Vec<(Access, Access)> {
+        let mut trips = Vec::new();
+        if !self.enabled || work.is_empty() {
+            return trips;
+        }
+
+        let (to_work, to_home) = self.per_hour(Demand::hour(tick));
+        let mut rng = rand::thread_rng();
+        for home in homes {
+            if rng.gen::<f64>() < to_work / TICKS_PER_HOUR as f64 {
+                let to = work.choose(&mut rng).unwrap();
+                trips.push((home.clone(), to.clone()));
+            }
+            if rng.gen::<f64>() < to_home / TICKS_PER_HOUR as f64 {
+                let from = work.choose(&mut rng).unwrap();
+                trips.push((from.clone(), home.clone()));
+            }
+        }
+        trips
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        assert!((TO_WORK.iter().sum::<f64>() - 1.0).abs() < 0.01);
+        assert!((TO_HOME.iter().sum::<f64>() - 1.0).abs() < 0.01);
+        assert_eq!(Demand::hour(TICKS_PER_HOUR * 31), 7);
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    curve::{Curve, SAMPLE_STEP},
+    intersection::IntersectionKind,
+    map::Map,
+    node::Node,
+    road::Road,
+};
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// Degrees to seven decimals, about a centimeter, never written as -0.
+fn degrees(value: f64) -> String {
+    format!("{:.7}", (value * 1e7).round() / 1e7 + 0.0)
+}
+
+// Longitude and latitude, as RFC 7946 asks. Maps that weren't imported are
+// placed at the default geo reference, a meter per map unit.
+fn position(map: &Map, n: &Node) -> String {
+    let (lat, lon) = map.geo.unwrap_or_default().unproject(n);
+    format!("[{},{}]", degrees(lon), degrees(lat))
+}
+
+fn line_string(map: &Map, curve: &Curve) -> String {
+    let length = curve.length();
+    let steps = (length / SAMPLE_STEP).ceil().max(1.0) as usize;
+    let points: Vec<String> = (0..=steps)
+        .map(|i| position(map, &curve.position_at(length * i as f64 / steps as f64)))
+        .collect();
+    format!("{{\"type\":\"LineString\",\"coordinates\":[{}]}}", points.join(","))
+}
+
+fn feature(geometry: String, properties: &[(&str, String)]) -> String {
+    let properties: Vec<String> = properties
+        .iter()
+        .map(|(key, value)| format!("{}:{}", json_string(key), value))
+        .collect();
+    format!(
+        "{{\"type\":\"Feature\",\"geometry\":{},\"properties\":{{{}}}}}",
+        geometry,
+        properties.join(",")
+    )
+}
+
+// Roads, lanes, intersections and properties as a FeatureCollection, the
+// "feature" property tells them apart.
+pub fn to_geojson(map: &Map) -> String {
+    let mut features = Vec::new();
+    let index_of = |road: &Arc<Mutex<Road>>| map.roads.iter().position(|r| Arc::ptr_eq(r, road));
+
+    for (i, intersection) in map.intersections.iter().enumerate() {
+        let intersection = intersection.lock().unwrap();
+        let (kind, radius) = match intersection.kind {
+            IntersectionKind::Point => ("Point", String::from("null")),
+            IntersectionKind::Roundabout { radius } => ("Roundabout", format!("{:.2}", radius)),
+        };
+        let roads: Vec<String> = intersection
+            .roads
+            .iter()
+            .filter_map(index_of)
+            .map(|r| r.to_string())
+            .collect();
+        features.push(feature(
+            format!("{{\"type\":\"Point\",\"coordinates\":{}}}", position(map, &intersection.center)),
+            &[
+                ("feature", json_string("intersection")),
+                ("id", i.to_string()),
+                ("kind", json_string(kind)),
+                ("radius", radius),
+                ("roads", format!("[{}]", roads.join(","))),
+            ],
+        ));
+
+        for lane in &intersection.lanes {
+            let lane = lane.lock().unwrap();
+            features.push(feature(
+                line_string(map, &lane.curve),
+                &[
+                    ("feature", json_string("lane")),
+                    ("lane_kind", json_string(lane.kind.name())),
+                    ("width", format!("{:.2}", lane.width)),
+                    ("speed_limit", format!("{:.2}", lane.speed_limit)),
+                    ("intersection", i.to_string()),
+                ],
+            ));
+        }
+    }
+
+    for (r, road) in map.roads.iter().enumerate() {
+        let road = road.lock().unwrap();
+        features.push(feature(
+            line_string(map, &road.curve),
+            &[
+                ("feature", json_string("road")),
+                ("id", r.to_string()),
+                ("kind", json_string(road.kind.name())),
+                ("profile", json_string(&road.profile.to_line())),
+                ("width", format!("{:.2}", road.width)),
+                ("lanes", road.lanes.len().to_string()),
+            ],
+        ));
+
+        for lane in &road.lanes {
+            let lane = lane.lock().unwrap();
+            features.push(feature(
+                line_string(map, &lane.curve),
+                &[
+                    ("feature", json_string("lane")),
+                    ("lane_kind", json_string(lane.kind.name())),
+                    ("width", format!("{:.2}", lane.width)),
+                    ("speed_limit", format!("{:.2}", lane.speed_limit)),
+                    ("road", r.to_string()),
+                ],
+            ));
+        }
+
+        for property in &road.properties {
+            let corners = [property.n0, property.n1, property.n2, property.n3, property.n0];
+            let ring: Vec<String> = corners.iter().map(|n| position(map, n)).collect();
+            features.push(feature(
+                format!("{{\"type\":\"Polygon\",\"coordinates\":[[{}]]}}", ring.join(",")),
+                &[
+                    ("feature", json_string("property")),
+                    ("kind", json_string(property.kind.name())),
+                    ("road", r.to_string()),
+                ],
+            ));
+        }
+    }
+
+    format!("{{\"type\":\"FeatureCollection\",\"features\":[\n{}\n]}}\n", features.join(",\n"))
+}
+
+pub fn save(map: &Map, path: &str) -> io::Result<()> {
+    fs::write(path, to_geojson(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network;
+
+    #[test]
+    fn test_geojson() {
+        let map = network::parse(
+            "intersection 0 0\n\
+             intersection 96 0\n\
+             road 0 1 Residential One tile: Car | Car\n\
+             zone 0 0 Commercial\n",
+        )
+        .unwrap();
+        let json = to_geojson(&map);
+        let count = |text: &str| json.matches(text).count();
+
+        let road = map.roads[0].lock().unwrap();
+        let lanes: usize = map.intersections.iter().map(|i| i.lock().unwrap().lanes.len()).sum::<usize>()
+            + road.lanes.len();
+        assert_eq!(count("\"feature\":\"intersection\""), 2);
+        assert_eq!(count("\"feature\":\"road\""), 1);
+        assert_eq!(count("\"feature\":\"lane\""), lanes);
+        assert_eq!(count("\"feature\":\"property\""), road.properties.len());
+        assert_eq!(count("\"kind\":\"Commercial\""), 1);
+        assert_eq!(count("{"), count("}"));
+        assert_eq!(count("["), count("]"));
+
+        // Next to 0° N 0° E, north up and east right
+        assert!(json.contains("\"coordinates\":[-0.0001439,0.0001439]}"));
+        assert!(json.contains("\"coordinates\":[0.0007195,0.0001439]}"));
+        assert_eq!(degrees(-0.00000001), "0.0000000");
+        assert_eq!(json_string("a \"b\"\n"), "\"a \\\"b\\\"\\n\"");
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    connection::{Connection, ConnectionKind},
+    lane::{Lane, LaneKind},
+    map::{key, Map},
+    validate::all_lanes,
+};
+
+// Network agents on a kind of lane move in. Sidewalks and tram tracks never
+// join the roads, everything else on the road does somewhere.
+fn network(kind: LaneKind) -> LaneKind {
+    match kind {
+        LaneKind::Pedestrian | LaneKind::Tram => kind,
+        _ => LaneKind::Car,
+    }
+}
+
+// Lanes each lane leads to, by position in lanes.
+fn successors(lanes: &[Arc<Mutex<Lane>>]) -> Vec<Vec<usize>> {
+    let index: HashMap<usize, usize> = lanes.iter().enumerate().map(|(i, l)| (key(l), i)).collect();
+    lanes
+        .iter()
+        .map(|l| {
+            let c1 = l.lock().unwrap().c1.clone();
+            let out_lanes = c1.lock().unwrap().out_lane.clone();
+            out_lanes.iter().filter_map(|o| index.get(&key(o)).copied()).collect()
+        })
+        .collect()
+}
+
+// Tarjan's algorithm, returns the component of each lane. Written without
+// recursion so big maps don't run out of stack.
+fn tarjan(successors: &[Vec<usize>]) -> Vec<usize> {
+    let n = successors.len();
+    let mut index = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut component = vec![usize::MAX; n];
+    let mut next_index = 0;
+    let mut next_component = 0;
+
+    for root in 0..n {
+        if index[root] != usize::MAX {
+            continue;
+        }
+        // Lanes being visited, with the next successor to look at
+        let mut work = vec![(root, 0)];
+        while let Some((v, i)) = work.pop() {
+            if i == 0 {
+                index[v] = next_index;
+                low[v] = next_index;
+                next_index += 1;
+                stack.push(v);
+                on_stack[v] = true;
+            }
+            if i < successors[v].len() {
+                let w = successors[v][i];
+                work.push((v, i + 1));
+                if index[w] == usize::MAX {
+                    work.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = low[v].min(index[w]);
+                }
+            } else {
+                if low[v] == index[v] {
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        component[w] = next_component;
+                        if w == v {
+                            break;
+                        }
+                    }
+                    next_component += 1;
+                }
+                // Back in the lane we came from
+                if let Some(&(u, _)) = work.last() {
+                    low[u] = low[u].min(low[v]);
+                }
+            }
+        }
+    }
+    component
+}
+
+// Groups of lanes that can all reach each other, biggest first.
+pub fn strongly_connected_components(lanes: &[Arc<Mutex<Lane>>]) -> Vec<Vec<Arc<Mutex<Lane>>>> {
+    let component = tarjan(&successors(lanes));
+    let mut groups: HashMap<usize, Vec<Arc<Mutex<Lane>>>> = HashMap::new();
+    for (i, lane) in lanes.iter().enumerate() {
+        groups.entry(component[i]).or_default().push(lane.clone());
+    }
+    let mut groups: Vec<Vec<Arc<Mutex<Lane>>>> = groups.into_values().collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.len()));
+    groups
+}
+
+// Lanes agents can get onto but never leave again. Each network has a main
+// component, the biggest one. A lane is trapped when it can't get there and
+// doesn't lead off the map at a sink. Lanes nobody travels on are left out.
+pub fn trapped_lanes(lanes: &[Arc<Mutex<Lane>>]) -> Vec<Arc<Mutex<Lane>>> {
+    let lanes: Vec<Arc<Mutex<Lane>>> =
+        lanes.iter().filter(|l| l.lock().unwrap().kind.is_traversable()).cloned().collect();
+    let successors = successors(&lanes);
+    let component = tarjan(&successors);
+    let n = lanes.len();
+
+    let network: Vec<LaneKind> = lanes.iter().map(|l| network(l.lock().unwrap().kind)).collect();
+
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for c in &component {
+        *sizes.entry(*c).or_default() += 1;
+    }
+    // Main component of each network
+    let mut main: HashMap<LaneKind, usize> = HashMap::new();
+    for v in 0..n {
+        let best = main.entry(network[v]).or_insert(component[v]);
+        if sizes[&component[v]] > sizes[best] {
+            *best = component[v];
+        }
+    }
+
+    // Walk the lanes backwards from the main components and the sinks
+    let mut predecessors = vec![Vec::new(); n];
+    for (v, next) in successors.iter().enumerate() {
+        for &w in next {
+            predecessors[w].push(v);
+        }
+    }
+    let mut escapes = vec![false; n];
+    let mut queue = Vec::new();
+    for v in 0..n {
+        let c1 = lanes[v].lock().unwrap().c1.clone();
+        let sink = c1.lock().unwrap().sink;
+        if component[v] == main[&network[v]] || sink {
+            escapes[v] = true;
+            queue.push(v);
+        }
+    }
+    while let Some(w) = queue.pop() {
+        for &v in &predecessors[w] {
+            if !escapes[v] {
+                escapes[v] = true;
+                queue.push(v);
+            }
+        }
+    }
+
+    lanes
+        .iter()
+        .enumerate()
+        .filter(|(v, _)| !escapes[*v])
+        .map(|(_, l)| l.clone())
+        .collect()
+}
+
+// Add the turning movements missing where trapped lanes arrive at an
+// intersection, until nothing is trapped or nothing more can be added.
+// Returns the number of lanes added.
+pub fn add_missing_movements(map: &Map) -> usize {
+    let mut added = 0;
+    loop {
+        let mut entries: Vec<Arc<Mutex<Connection>>> = Vec::new();
+        for lane in trapped_lanes(&all_lanes(map)) {
+            let c1 = lane.lock().unwrap().c1.clone();
+            let is_entry = c1.lock().unwrap().kind == ConnectionKind::In;
+            if is_entry && !entries.iter().any(|c| Arc::ptr_eq(c, &c1)) {
+                entries.push(c1);
+            }
+        }
+
+        let mut added_now = 0;
+        for c0 in &entries {
+            for intersection in &map.intersections {
+                let mut intersection = intersection.lock().unwrap();
+                if intersection.connections.iter().any(|c| Arc::ptr_eq(c, c0)) {
+                    added_now += intersection.add_missing_movements(c0);
+                    break;
+                }
+            }
+        }
+        if added_now == 0 {
+            return added;
+        }
+        added += added_now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network;
+
+    #[test]
+    fn test_trapped_lanes() {
+        // The bike lane runs into a road with nothing but bus lanes
+        let mut map = network::parse(
+            "intersection 0 0\n\
+             intersection 96 0\n\
+             intersection 192 0\n\
+             road 0 1 Residential Bike one way: Car Bike | Car\n\
+             road 1 2 Residential Bus only: Bus | Bus\n",
+        )
+        .unwrap();
+        let lanes = all_lanes(&map);
+        assert_eq!(strongly_connected_components(&lanes)[0].len(), lanes.len() - 1);
+        assert_eq!(map.trapped_lanes().len(), 1);
+
+        assert!(map.add_missing_movements() > 0);
+        assert!(map.trapped_lanes().is_empty());
+
+        // The new movements are kept when the intersection is rebuilt
+        map.intersections[1].lock().unwrap().add_lanes();
+        assert!(map.trapped_lanes().is_empty());
+
+        // And saved with the network
+        let saved = network::parse(&network::to_text(&map)).unwrap();
+        assert!(saved.trapped_lanes().is_empty());
+    }
+
+    #[test]
+    fn test_untraveled_lanes() {
+        let mut map = network::parse(
+            "intersection 0 0\n\
+             intersection 96 0\n\
+             intersection 192 0\n\
+             road 0 1 Residential Boulevard: Car Median | Car Parking\n\
+             road 1 2 Residential Boulevard: Car Median | Car Parking\n",
+        )
+        .unwrap();
+        // Medians and parking lanes lead nowhere, but nobody drives on them
+        assert!(map.trapped_lanes().is_empty());
+        assert_eq!(map.add_missing_movements(), 0);
+        assert!(map.intersections.iter().all(|i| i.lock().unwrap().movements.is_empty()));
+    }
+}
@@ -0,0 +1,269 @@
+// Just enough zlib for PBF extracts: a two byte header, deflate blocks and
+// the Adler-32 checksum of what they hold.
+
+// Lengths for length codes 257 to 285 and the extra bits added to them
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+// Distances for distance codes 0 to 29 and the extra bits added to them
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order the lengths of the code length code come in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Deflate data read a bit at a time, lowest bit of each byte first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> Result<u32, String> {
+        let byte = *self.data.get(self.pos).ok_or("Truncated deflate data")?;
+        let bit = (byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(bit as u32)
+    }
+
+    fn bits(&mut self, n: u8) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..n {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    // Skip to the next whole byte
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&[u8], String> {
+        let bytes = self.data.get(self.pos..self.pos + n).ok_or("Truncated deflate data")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+}
+
+// A canonical Huffman code, as the number of codes of each length and the
+// symbols in the order of their codes.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    // From the code length of each symbol, 0 for unused symbols.
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; 16];
+        for length in 1..16 {
+            offsets[length] = offsets[length - 1] + counts[length - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length > 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        // First code of each length and where its symbols start
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= bits.bit()? as usize;
+            let count = self.counts[length] as usize;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(String::from("Bad Huffman code"))
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1, 0);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+pub fn zlib(data: &[u8]) -> Result<Vec<u8>, String> {
+    let (cmf, flg) = match data {
+        [cmf, flg, ..] => (*cmf, *flg),
+        _ => return Err(String::from("Truncated zlib data")),
+    };
+    if cmf & 0x0f != 8 || (cmf as u16 * 256 + flg as u16) % 31 != 0 {
+        return Err(String::from("Not zlib data"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(String::from("zlib preset dictionaries are not supported"));
+    }
+
+    let mut bits = Bits { data: &data[2..], pos: 0, bit: 0 };
+    let inflated = inflate(&mut bits)?;
+    bits.align();
+    let checksum = bits.bytes(4).map_err(|_| "Missing zlib checksum")?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&inflated) {
+        return Err(String::from("zlib checksum mismatch"));
+    }
+    Ok(inflated)
+}
+
+fn inflate(bits: &mut Bits) -> Result<Vec<u8>, String> {
+    let mut inflated = Vec::new();
+    loop {
+        let last = bits.bit()? == 1;
+        match bits.bits(2)? {
+            0 => {
+                // Stored as is, after the length and its complement
+                bits.align();
+                let header = bits.bytes(4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(String::from("Bad stored block length"));
+                }
+                inflated.extend_from_slice(bits.bytes(length as usize)?);
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(bits, &mut inflated, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(bits)?;
+                inflate_block(bits, &mut inflated, &literals, &distances)?;
+            }
+            _ => return Err(String::from("Bad deflate block type")),
+        }
+        if last {
+            return Ok(inflated);
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8; 288];
+    lengths[144..256].iter_mut().for_each(|l| *l = 9);
+    lengths[256..280].iter_mut().for_each(|l| *l = 7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+// Codes given at the start of the block, their lengths coded themselves.
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_length_count = bits.bits(4)? as usize + 4;
+    let mut code_lengths = [0; 19];
+    for i in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[*i] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::new();
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_lengths.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or("Nothing to repeat")?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat(length).take(repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(String::from("Too many code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+// Literal bytes and copies of earlier ones, up to the end of block code.
+fn inflate_block(bits: &mut Bits, inflated: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol < 256 {
+            inflated.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let code = symbol - 257;
+        if code >= LENGTH_BASE.len() {
+            return Err(String::from("Bad length code"));
+        }
+        let length = LENGTH_BASE[code] as usize + bits.bits(LENGTH_EXTRA[code])? as usize;
+        let code = distances.decode(bits)? as usize;
+        if code >= DISTANCE_BASE.len() {
+            return Err(String::from("Bad distance code"));
+        }
+        let distance = DISTANCE_BASE[code] as usize + bits.bits(DISTANCE_EXTRA[code])? as usize;
+        if distance > inflated.len() {
+            return Err(String::from("Copy from before the start"));
+        }
+        let start = inflated.len() - distance;
+        for i in start..start + length {
+            let byte = inflated[i];
+            inflated.push(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zlib() {
+        // Stored, fixed codes and dynamic codes, as written by zlib
+        let stored = [
+            0x78, 0x01, 0x01, 0x06, 0x00, 0xf9, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x09, 0x3c, 0x02, 0x92,
+        ];
+        assert_eq!(zlib(&stored).unwrap(), b"stored");
+        let fixed = [0x78, 0xda, 0x2b, 0xca, 0x4f, 0x4c, 0x29, 0x06, 0x00, 0x06, 0x59, 0x02, 0x1a];
+        assert_eq!(zlib(&fixed).unwrap(), b"roads");
+        let dynamic = [
+            0x78, 0xda, 0x4d, 0x8e, 0x5d, 0x0a, 0xc0, 0x20, 0x0c, 0x83, 0xaf, 0xd2, 0xab, 0xd9, 0x59, 0x86, 0x4c, 0x74,
+            0x54, 0xf7, 0xb0, 0xdb, 0x8f, 0x26, 0x30, 0x7d, 0xf0, 0xa3, 0x3f, 0x49, 0xa3, 0x96, 0xcb, 0x64, 0xcc, 0x7e,
+            0x13, 0x35, 0x35, 0x23, 0xf4, 0x19, 0x78, 0x68, 0xde, 0x62, 0x35, 0xb3, 0xf4, 0xd2, 0x4e, 0x39, 0x92, 0x8b,
+            0xf7, 0x94, 0x69, 0xc2, 0x08, 0xa0, 0x22, 0x16, 0x44, 0xcc, 0xe8, 0x85, 0x50, 0x23, 0x6c, 0xf3, 0x45, 0xb5,
+            0x12, 0xd1, 0x52, 0x12, 0xbe, 0xf5, 0xa9, 0x3f, 0x8d, 0xa7, 0x76, 0x62, 0x15, 0xea, 0x0f, 0x51, 0x01, 0x47,
+            0xef,
+        ];
+        // The checksum vouches for the rest
+        let text = zlib(&dynamic).unwrap();
+        assert_eq!(text.len(), 200);
+        assert!(text.starts_with(b"bike stop stop lane"));
+
+        let mut broken = fixed;
+        broken[12] ^= 1;
+        assert!(zlib(&broken).is_err());
+        assert!(zlib(&fixed[..8]).is_err());
+    }
+}
@@ -6,99 +6,406 @@ use std::{
 use cairo::Context;
 
 use crate::{
-    connection::{Connection, ConnectionKind},
+    connection::{Connection, ConnectionKind, SignKind},
     curve::Curve,
     lane::{Lane, LaneKind, self},
     node::Node,
-    road::Road, road_profile::{self, RoadProfile},
+    road::{Road, RoadKind},
 };
 
+// Kind of the intersection lane joining two kinds of lanes, if they can be joined.
+fn lane_kind_between(k0: LaneKind, k1: LaneKind) -> Option<LaneKind> {
+    match (k0, k1) {
+        // Buses merge in and out of regular traffic
+        (LaneKind::Bus, LaneKind::Car) | (LaneKind::Car, LaneKind::Bus) => Some(LaneKind::Bus),
+        (k0, k1) if k0 == k1 => Some(k0),
+        _ => None,
+    }
+}
+
+// Kind of an added turning movement, cyclists may also merge onto a car lane.
+fn movement_kind(k0: LaneKind, k1: LaneKind) -> Option<LaneKind> {
+    match (k0, k1) {
+        (k0, _) if !k0.is_traversable() => None,
+        (LaneKind::Bike, LaneKind::Car) => Some(LaneKind::Bike),
+        (k0, k1) => lane_kind_between(k0, k1),
+    }
+}
+
+// Lanes whose traffic goes around a roundabout, trams cross it
+fn rides_ring(kind: LaneKind) -> bool {
+    matches!(kind, LaneKind::Car | LaneKind::Bus | LaneKind::Bike)
+}
+
+// Distance kept free in front of a roundabout entry
+const ROUNDABOUT_GAP: f64 = 12.0;
+// Pedestrians this close to a crosswalk are about to step on it
+const CROSSWALK_GAP: f64 = 3.0;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum IntersectionKind {
+    Point,
+    Roundabout { radius: f64 },
+}
+
 pub struct Intersection {
     pub center: Node,
+    pub kind: IntersectionKind,
     pub roads: Vec<Arc<Mutex<Road>>>,
     pub connections: Vec<Arc<Mutex<Connection>>>,
     pub lanes: Vec<Arc<Mutex<Lane>>>,
+    // Entry and exit points on the roundabout ring
+    pub ring: Vec<Arc<Mutex<Connection>>>,
+    // Pedestrian lanes crossing the attached roads
+    pub crosswalks: Vec<Arc<Mutex<Lane>>>,
+    // Turning movements added on top of the usual ones, as (from, to, lane kind)
+    pub movements: Vec<(Arc<Mutex<Connection>>, Arc<Mutex<Connection>>, LaneKind)>,
 }
 
 impl Intersection {
     pub fn new(x: f64, y: f64) -> Self {
         Self {
             center: Node::new(x, y),
+            kind: IntersectionKind::Point,
             roads: Vec::new(),
             connections: Vec::new(),
             lanes: Vec::new(),
+            ring: Vec::new(),
+            crosswalks: Vec::new(),
+            movements: Vec::new(),
+        }
+    }
+
+    pub fn new_roundabout(x: f64, y: f64, radius: f64) -> Self {
+        let mut intersection = Intersection::new(x, y);
+        intersection.kind = IntersectionKind::Roundabout { radius };
+        intersection
+    }
+
+    pub fn set_kind(&mut self, kind: IntersectionKind) {
+        if kind == IntersectionKind::Point {
+            // Drop the yield signs of the ring entries
+            for c in self.ring_entries() {
+                let mut c = c.lock().unwrap();
+                c.sign = SignKind::None;
+                c.waiting.clear();
+                c.cleared = None;
+            }
+        }
+        self.kind = kind;
+        self.add_lanes();
+    }
+
+    // Distance from the center to where roads attach.
+    pub fn arm_length(&self, road_width: f64) -> f64 {
+        match self.kind {
+            IntersectionKind::Point => road_width,
+            IntersectionKind::Roundabout { radius } => road_width.max(radius + 8.0),
         }
     }
 
     pub fn draw(&self, context: &Context) {
+        if let IntersectionKind::Roundabout { .. } = self.kind {
+            // Central island
+            let radius = self.ring_radius() - 2.0;
+            context.set_source_rgb(0.30, 0.50, 0.30);
+            context.arc(self.center.x, self.center.y, radius, 0.0, PI * 2.0);
+            context.fill().expect("Failed to draw roundabout!");
+        }
         self.center.draw(context, 2.5);
         for lane in &self.lanes {
             lane.lock().unwrap().draw(context);
         }
+        for crosswalk in &self.crosswalks {
+            // Zebra stripes
+            context.save().expect("Failed to draw crosswalk!");
+            context.set_source_rgb(0.95, 0.95, 0.95);
+            context.set_line_width(3.0);
+            context.set_dash(&[0.6, 0.6], 0.0);
+            crosswalk.lock().unwrap().curve.plot(context);
+            context.stroke().expect("Failed to draw crosswalk!");
+            context.restore().expect("Failed to draw crosswalk!");
+        }
+        for connection in &self.connections {
+            connection.lock().unwrap().draw(context);
+        }
+    }
+
+    // Incoming car connections, the ones that can carry signs.
+    fn signable_connections(&self) -> Vec<Arc<Mutex<Connection>>> {
+        self.connections
+            .iter()
+            .filter(|c| {
+                let c = c.lock().unwrap();
+                c.kind == ConnectionKind::In && c.lane_kind == LaneKind::Car
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Incoming connections that enter the roundabout ring
+    fn ring_entries(&self) -> Vec<Arc<Mutex<Connection>>> {
+        self.connections
+            .iter()
+            .filter(|c| {
+                let c = c.lock().unwrap();
+                c.kind == ConnectionKind::In && rides_ring(c.lane_kind)
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn is_all_way_stop(&self) -> bool {
+        let connections = self.signable_connections();
+        !connections.is_empty()
+            && connections
+                .iter()
+                .all(|c| c.lock().unwrap().sign == SignKind::Stop)
     }
 
-    pub fn get_connections(&mut self, a: f64, road_profile: Arc<Mutex<RoadProfile>>) -> Vec<Arc<Mutex<Connection>>> {
-        let width = road_profile.lock().unwrap().width() / 2.0;
-        let mut cs: Vec<Arc<Mutex<Connection>>> = Vec::new();
-        
-        let a0 = a + PI / 2.0;
-        let a1 = a - PI / 2.0;
-        
+    pub fn set_signs(&mut self, sign: SignKind) {
+        for connection in self.signable_connections() {
+            let mut connection = connection.lock().unwrap();
+            connection.sign = sign;
+            if sign == SignKind::None {
+                connection.waiting.clear();
+                connection.cleared = None;
+            }
+        }
+    }
+
+    pub fn toggle_all_way_stop(&mut self) {
+        if self.is_all_way_stop() {
+            self.set_signs(SignKind::None);
+        } else {
+            self.set_signs(SignKind::Stop);
+        }
+    }
+
+    // The incoming car connection closest to n, if any is near.
+    fn connection_at(&self, n: &Node, max_distance: f64) -> Option<Arc<Mutex<Connection>>> {
+        self.signable_connections()
+            .into_iter()
+            .map(|c| {
+                let d = c.lock().unwrap().center.distance(n);
+                (c, d)
+            })
+            .filter(|(_, d)| *d < max_distance)
+            .min_by(|(_, d0), (_, d1)| d0.partial_cmp(d1).unwrap())
+            .map(|(c, _)| c)
+    }
+
+    // Cycle the sign of the incoming connection closest to n, if any is near.
+    pub fn cycle_sign_at(&mut self, n: &Node, max_distance: f64) -> bool {
+        match self.connection_at(n, max_distance) {
+            Some(c) => {
+                let mut c = c.lock().unwrap();
+                c.sign = c.sign.next();
+                c.waiting.clear();
+                c.cleared = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Make the incoming connection closest to n a sink, or a regular one again.
+    pub fn toggle_sink_at(&mut self, n: &Node, max_distance: f64) -> bool {
+        match self.connection_at(n, max_distance) {
+            Some(c) => {
+                let mut c = c.lock().unwrap();
+                c.sink = !c.sink;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Agents that left the map, or were moved off the lane they waited on
+    pub fn forget_agents(&self, ids: &[usize]) {
+        for c in &self.connections {
+            let mut c = c.lock().unwrap();
+            for id in ids {
+                c.forget(*id);
+            }
+        }
+    }
+
+    // Let agents waiting at signs enter once it is safe.
+    pub fn update(&mut self) {
+        self.update_crosswalks();
+
+        // Whoever was let through has to still be on the way in
+        for c in &self.connections {
+            let mut c = c.lock().unwrap();
+            if let Some(id) = c.cleared {
+                let arriving = c
+                    .in_lane
+                    .iter()
+                    .any(|l| l.lock().unwrap().occupants.iter().any(|(o, _, _)| *o == id));
+                if !arriving {
+                    c.forget(id);
+                }
+            }
+        }
+
+        if let IntersectionKind::Roundabout { .. } = self.kind {
+            self.update_roundabout();
+            return;
+        }
+
+        let connections = self.signable_connections();
+
+        // Someone was let through but has not entered yet
+        if connections
+            .iter()
+            .any(|c| c.lock().unwrap().cleared.is_some())
+        {
+            return;
+        }
+
+        // Only one vehicle at a time in the intersection
+        if self.lanes.iter().any(|l| {
+            let l = l.lock().unwrap();
+            l.kind != LaneKind::Pedestrian && l.is_occupied()
+        }) {
+            return;
+        }
+
+        // Traffic on approaches without a sign has the right of way
+        if !self.is_all_way_stop() {
+            let approaching = connections.iter().any(|c| {
+                let c = c.lock().unwrap();
+                c.sign == SignKind::None
+                    && c.in_lane.iter().any(|l| {
+                        let l = l.lock().unwrap();
+                        let length = l.length();
+                        l.occupants.iter().any(|(_, d, _)| length - d < 20.0)
+                    })
+            });
+            if approaching {
+                return;
+            }
+        }
+
+        // First come, first served
+        let first = connections
+            .iter()
+            .filter_map(|c| {
+                let c_lock = c.lock().unwrap();
+                c_lock.waiting.first().map(|(id, tick)| (c.clone(), *id, *tick))
+            })
+            .min_by_key(|(_, _, tick)| *tick);
+
+        if let Some((c, id, _)) = first {
+            c.lock().unwrap().cleared = Some(id);
+        }
+    }
+
+    // Connections for a road attached in direction a. Lanes leaving the
+    // intersection are on the right, arriving lanes on the left, both
+    // listed from the center of the road and outwards.
+    pub fn get_connections(
+        &mut self,
+        a: f64,
+        road_width: f64,
+        out_lane_kinds: &[LaneKind],
+        in_lane_kinds: &[LaneKind],
+    ) -> (Vec<Arc<Mutex<Connection>>>, Vec<Arc<Mutex<Connection>>>) {
+        let center = self.center.offset(a, self.arm_length(road_width));
+
+        let mut outs: Vec<Arc<Mutex<Connection>>> = Vec::new();
         let mut offset = 0.0;
-        for lane_kind in &road_profile.lock().unwrap().right_lane_kinds {
-            let lane_width = match lane_kind {
-                LaneKind::Car => 4.0,
-                LaneKind::Bike => 2.0,
-                LaneKind::Pedestrian => 2.0,
-            };
+        for lane_kind in out_lane_kinds {
+            let lane_width = lane_kind.width();
             offset += lane_width / 2.0;
-            let n0 = self.center.offset(a, width * 2.0).offset(a0, offset);
+            let n0 = center.offset(a + PI / 2.0, offset);
             let c0 = Arc::new(Mutex::new(Connection::new(
                 n0,
                 ConnectionKind::Out,
                 *lane_kind,
                 a,
-                width / 2.0,
+                offset,
             )));
             offset += lane_width / 2.0;
-            cs.push(c0.clone());
-            self.connections.push(c0.clone());
+            outs.push(c0.clone());
+            self.connections.push(c0);
         }
+
+        let mut ins: Vec<Arc<Mutex<Connection>>> = Vec::new();
         let mut offset = 0.0;
-        for lane_kind in &road_profile.lock().unwrap().left_lane_kinds {
-            let lane_width = match lane_kind {
-                LaneKind::Car => 4.0,
-                LaneKind::Bike => 2.0,
-                LaneKind::Pedestrian => 2.0,               
-            };
+        for lane_kind in in_lane_kinds {
+            let lane_width = lane_kind.width();
             offset += lane_width / 2.0;
-            let n1 = self.center.offset(a, width * 2.0).offset(a1, offset);
+            let n1 = center.offset(a - PI / 2.0, offset);
             let c1 = Arc::new(Mutex::new(Connection::new(
                 n1,
                 ConnectionKind::In,
                 *lane_kind,
                 a,
-                width / 2.0,
+                offset,
             )));
             offset += lane_width / 2.0;
-            cs.push(c1.clone());
-            self.connections.push(c1.clone());
+            ins.push(c1.clone());
+            self.connections.push(c1);
         }
 
+        (outs, ins)
+    }
+
+    // Forget a road and the connections it used.
+    pub fn remove_road(&mut self, road: &Arc<Mutex<Road>>, connections: &[Arc<Mutex<Connection>>]) {
+        self.roads.retain(|r| !Arc::ptr_eq(r, road));
+        let removed: Vec<Arc<Mutex<Connection>>> = self
+            .connections
+            .iter()
+            .filter(|c| connections.iter().any(|o| Arc::ptr_eq(c, o)))
+            .cloned()
+            .collect();
+        if removed.is_empty() {
+            return;
+        }
+        self.connections.retain(|c| !removed.iter().any(|o| Arc::ptr_eq(c, o)));
         self.add_lanes();
-        cs
     }
 
     pub fn add_lanes(&mut self) {
+        // Detach the old lanes from the connections
+        for c in &self.connections {
+            let mut c_lock = c.lock().unwrap();
+            c_lock.out_lane.retain(|l| !self.lanes.iter().any(|o| Arc::ptr_eq(l, o)));
+            c_lock.in_lane.retain(|l| !self.lanes.iter().any(|o| Arc::ptr_eq(l, o)));
+        }
         self.lanes.clear();
+        self.ring.clear();
+        self.crosswalks.clear();
+
+        let is_roundabout = match self.kind {
+            IntersectionKind::Point => false,
+            IntersectionKind::Roundabout { .. } => true,
+        };
+
+        let speed_limit = self.speed_limit();
+
         // Setup lanes for new connection:
         for c0 in &self.connections {
             for c1 in &self.connections {
                 if !Arc::ptr_eq(c0, c1) {
                     let mut c0_lock = c0.lock().unwrap();
                     let mut c1_lock = c1.lock().unwrap();
+                    if !c0_lock.lane_kind.is_traversable() {
+                        continue;
+                    }
+                    if c0_lock.lane_kind == LaneKind::Pedestrian {
+                        // Pedestrians walk around the corners
+                        continue;
+                    }
+                    if is_roundabout && (rides_ring(c0_lock.lane_kind) || rides_ring(c1_lock.lane_kind)) {
+                        // Cars, buses and bikes go around the ring
+                        continue;
+                    }
                     if c0_lock.kind == ConnectionKind::In && c1_lock.kind == ConnectionKind::Out {
-                        if c0_lock.lane_kind == c1_lock.lane_kind {
+                        if let Some(lane_kind) = lane_kind_between(c0_lock.lane_kind, c1_lock.lane_kind) {
                             let curve = Curve::new(
                                 c0_lock.center,
                                 c1_lock.center,
@@ -110,7 +417,8 @@ impl Intersection {
                                 c1.clone(),
                                 curve,
                                 5.0,
-                                c0_lock.lane_kind,
+                                lane_kind,
+                                speed_limit,
                             )));
                             c0_lock.out_lane.push(l.clone());
                             c1_lock.in_lane.push(l.clone());
@@ -120,5 +428,711 @@ impl Intersection {
                 }
             }
         }
+
+        if is_roundabout {
+            self.add_ring_lanes();
+        } else {
+            self.add_bike_merges();
+        }
+        self.add_pedestrian_lanes();
+        self.add_turnarounds();
+        self.add_movements();
+    }
+
+    pub fn add_movement(&mut self, c0: Arc<Mutex<Connection>>, c1: Arc<Mutex<Connection>>, lane_kind: LaneKind) {
+        self.movements.push((c0, c1, lane_kind));
+        self.add_movements();
+    }
+
+    // Movements from an entry to the exits it doesn't reach yet, on the other
+    // roads or else back on its own. Returns the number added.
+    pub fn add_missing_movements(&mut self, c0: &Arc<Mutex<Connection>>) -> usize {
+        let (lane_kind, angle, out_lanes) = {
+            let c0 = c0.lock().unwrap();
+            (c0.lane_kind, c0.angle, c0.out_lane.clone())
+        };
+        let reached: Vec<Arc<Mutex<Connection>>> =
+            out_lanes.iter().map(|l| l.lock().unwrap().c1.clone()).collect();
+
+        // As (exit, lane kind, on the same road)
+        let mut missing = Vec::new();
+        for c1 in &self.connections {
+            if reached.iter().any(|r| Arc::ptr_eq(r, c1)) {
+                continue;
+            }
+            let c = c1.lock().unwrap();
+            if c.kind != ConnectionKind::Out {
+                continue;
+            }
+            if let Some(kind) = movement_kind(lane_kind, c.lane_kind) {
+                missing.push((c1.clone(), kind, (c.angle - angle).abs() < 0.01));
+            }
+        }
+        if missing.iter().any(|m| !m.2) {
+            missing.retain(|m| !m.2);
+        }
+
+        for (c1, kind, _) in &missing {
+            self.add_movement(c0.clone(), c1.clone(), *kind);
+        }
+        missing.len()
+    }
+
+    // Lanes for the added movements that are still possible and not there already.
+    fn add_movements(&mut self) {
+        let connections = &self.connections;
+        self.movements.retain(|(c0, c1, _)| {
+            connections.iter().any(|c| Arc::ptr_eq(c, c0)) && connections.iter().any(|c| Arc::ptr_eq(c, c1))
+        });
+
+        let speed_limit = self.speed_limit();
+        for (c0, c1, lane_kind) in &self.movements {
+            let mut c0_lock = c0.lock().unwrap();
+            if c0_lock.out_lane.iter().any(|l| Arc::ptr_eq(&l.lock().unwrap().c1, c1)) {
+                continue;
+            }
+            let mut c1_lock = c1.lock().unwrap();
+            let curve = Curve::new(c0_lock.center, c1_lock.center, c0_lock.angle, c1_lock.angle);
+            let l = Arc::new(Mutex::new(Lane::new(
+                c0.clone(),
+                c1.clone(),
+                curve,
+                lane_kind.width(),
+                *lane_kind,
+                speed_limit,
+            )));
+            c0_lock.out_lane.push(l.clone());
+            c1_lock.in_lane.push(l.clone());
+            self.lanes.push(l);
+        }
+    }
+
+    // At the end of a road, turn around onto the lane of the same kind going
+    // back, or else onto the road itself. What still has nowhere to go is a dead end.
+    fn add_turnarounds(&mut self) {
+        let mut arms: Vec<f64> = Vec::new();
+        for c in &self.connections {
+            let a = c.lock().unwrap().angle;
+            if !arms.iter().any(|arm| (arm - a).abs() < 0.01) {
+                arms.push(a);
+            }
+        }
+        if arms.len() != 1 {
+            return;
+        }
+
+        let speed_limit = self.speed_limit();
+        let mut turnarounds = Vec::new();
+        for c0 in &self.connections {
+            let c0_lock = c0.lock().unwrap();
+            let lane_kind = c0_lock.lane_kind;
+            let dead_end = c0_lock.kind == ConnectionKind::In
+                && lane_kind.is_traversable()
+                && c0_lock.out_lane.is_empty();
+            drop(c0_lock);
+            if !dead_end {
+                continue;
+            }
+            let target = self
+                .outermost(ConnectionKind::Out, lane_kind, arms[0])
+                .or_else(|| self.outermost(ConnectionKind::Out, LaneKind::Car, arms[0]));
+            if let Some(c1) = target {
+                turnarounds.push((c0.clone(), c1, lane_kind));
+            }
+        }
+
+        for (c0, c1, lane_kind) in turnarounds {
+            let mut c0_lock = c0.lock().unwrap();
+            let mut c1_lock = c1.lock().unwrap();
+            let curve = Curve::new(c0_lock.center, c1_lock.center, c0_lock.angle, c1_lock.angle);
+            let l = Arc::new(Mutex::new(Lane::new(
+                c0.clone(),
+                c1.clone(),
+                curve,
+                lane_kind.width(),
+                lane_kind,
+                speed_limit,
+            )));
+            c0_lock.out_lane.push(l.clone());
+            c1_lock.in_lane.push(l.clone());
+            self.lanes.push(l);
+        }
+    }
+
+    // Connection of this kind furthest from the center of the road attached in direction a.
+    fn outermost(&self, kind: ConnectionKind, lane_kind: LaneKind, a: f64) -> Option<Arc<Mutex<Connection>>> {
+        self.connections
+            .iter()
+            .filter(|c| {
+                let c = c.lock().unwrap();
+                c.lane_kind == lane_kind && c.kind == kind && (c.angle - a).abs() < 0.01
+            })
+            .max_by(|c0, c1| {
+                let o0 = c0.lock().unwrap().offset;
+                let o1 = c1.lock().unwrap().offset;
+                o0.partial_cmp(&o1).unwrap()
+            })
+            .cloned()
+    }
+
+    // Bike turn lanes to and from roads without a bike lane, cyclists
+    // merge with the outermost car lane there.
+    fn add_bike_merges(&mut self) {
+        let speed_limit = self.speed_limit();
+
+        let mut arms: Vec<f64> = Vec::new();
+        for c in &self.connections {
+            let a = c.lock().unwrap().angle;
+            if !arms.iter().any(|arm| (arm - a).abs() < 0.01) {
+                arms.push(a);
+            }
+        }
+
+        // Bike lane or else car lane, on each side of each road
+        let bike_or_car = |kind: ConnectionKind, a: f64| match self.outermost(kind, LaneKind::Bike, a) {
+            Some(c) => Some((c, true)),
+            None => self.outermost(kind, LaneKind::Car, a).map(|c| (c, false)),
+        };
+
+        let mut merges = Vec::new();
+        for a0 in &arms {
+            for a1 in &arms {
+                if (a0 - a1).abs() < 0.01 {
+                    continue;
+                }
+                let (c0, c1) = match (bike_or_car(ConnectionKind::In, *a0), bike_or_car(ConnectionKind::Out, *a1)) {
+                    (Some(c0), Some(c1)) => (c0, c1),
+                    _ => continue,
+                };
+                // Exactly one side has a bike lane, both is handled already
+                if c0.1 != c1.1 {
+                    merges.push((c0.0, c1.0));
+                }
+            }
+        }
+
+        for (c0, c1) in merges {
+            let mut c0_lock = c0.lock().unwrap();
+            let mut c1_lock = c1.lock().unwrap();
+            let curve = Curve::new(c0_lock.center, c1_lock.center, c0_lock.angle, c1_lock.angle);
+            let l = Arc::new(Mutex::new(Lane::new(
+                c0.clone(),
+                c1.clone(),
+                curve,
+                LaneKind::Bike.width(),
+                LaneKind::Bike,
+                speed_limit,
+            )));
+            c0_lock.out_lane.push(l.clone());
+            c1_lock.in_lane.push(l.clone());
+            self.lanes.push(l);
+        }
+    }
+
+    // Pedestrians cross each road on a crosswalk and turn the corners to
+    // the next road, they never walk across the middle.
+    fn add_pedestrian_lanes(&mut self) {
+        let speed_limit = self.speed_limit();
+
+        let mut arms: Vec<f64> = Vec::new();
+        for c in &self.connections {
+            let c = c.lock().unwrap();
+            if c.lane_kind == LaneKind::Pedestrian && !arms.iter().any(|arm| (arm - c.angle).abs() < 0.01) {
+                arms.push(c.angle);
+            }
+        }
+        arms.sort_by(|a0, a1| a0.partial_cmp(a1).unwrap());
+
+        let mut lanes = Vec::new();
+        let mut crosswalks = Vec::new();
+        for (i, arm) in arms.iter().enumerate() {
+            let c0 = match self.outermost(ConnectionKind::In, LaneKind::Pedestrian, *arm) {
+                Some(c0) => c0,
+                None => continue,
+            };
+
+            // Across the road
+            if let Some(c1) = self.outermost(ConnectionKind::Out, LaneKind::Pedestrian, *arm) {
+                let curve = Curve::new_tangent(
+                    c0.lock().unwrap().center,
+                    arm + PI / 2.0,
+                    c1.lock().unwrap().center,
+                );
+                crosswalks.push((c0.clone(), c1, curve));
+            }
+
+            // Around the corner, the next road on our side
+            let next = if i > 0 { arms[i - 1] } else { arms[arms.len() - 1] };
+            if arms.len() > 1 {
+                if let Some(c1) = self.outermost(ConnectionKind::Out, LaneKind::Pedestrian, next) {
+                    let curve = Curve::new(
+                        c0.lock().unwrap().center,
+                        c1.lock().unwrap().center,
+                        *arm,
+                        next,
+                    );
+                    lanes.push((c0.clone(), c1, curve));
+                }
+            }
+        }
+
+        for (is_crosswalk, (c0, c1, curve)) in crosswalks
+            .into_iter()
+            .map(|l| (true, l))
+            .chain(lanes.into_iter().map(|l| (false, l)))
+        {
+            let l = Arc::new(Mutex::new(Lane::new(
+                c0.clone(),
+                c1.clone(),
+                curve,
+                LaneKind::Pedestrian.width(),
+                LaneKind::Pedestrian,
+                speed_limit,
+            )));
+            c0.lock().unwrap().out_lane.push(l.clone());
+            c1.lock().unwrap().in_lane.push(l.clone());
+            if is_crosswalk {
+                self.crosswalks.push(l.clone());
+            }
+            self.lanes.push(l);
+        }
+    }
+
+    // Vehicles may not drive over a crosswalk while it is in use.
+    fn update_crosswalks(&mut self) {
+        let busy: Vec<f64> = self
+            .crosswalks
+            .iter()
+            .filter(|crosswalk| {
+                let crosswalk = crosswalk.lock().unwrap();
+                let c0 = crosswalk.c0.lock().unwrap();
+                let stepping_on = c0.in_lane.iter().any(|l| {
+                    let l = l.lock().unwrap();
+                    let length = l.length();
+                    l.occupants.iter().any(|(_, d, _)| length - d < CROSSWALK_GAP)
+                });
+                crosswalk.is_occupied() || stepping_on
+            })
+            .map(|crosswalk| crosswalk.lock().unwrap().c0.lock().unwrap().angle)
+            .collect();
+
+        for c in &self.connections {
+            let mut c = c.lock().unwrap();
+            if c.lane_kind == LaneKind::Pedestrian {
+                continue;
+            }
+            let angle = c.angle;
+            c.blocked = busy.iter().any(|a| (a - angle).abs() < 0.01);
+        }
+    }
+
+    // Let waiting agents onto the ring when there is a gap.
+    fn update_roundabout(&mut self) {
+        for c in self.ring_entries() {
+            let mut c_lock = c.lock().unwrap();
+            if c_lock.cleared.is_some() {
+                continue;
+            }
+            let id = match c_lock.waiting.first() {
+                Some((id, _)) => *id,
+                None => continue,
+            };
+
+            let entries: Vec<Arc<Mutex<Connection>>> = c_lock
+                .out_lane
+                .iter()
+                .map(|l| l.lock().unwrap().c1.clone())
+                .collect();
+            let blocked = entries.iter().any(|entry| {
+                let entry = entry.lock().unwrap();
+                let approaching = entry.in_lane.iter().any(|l| {
+                    let l = l.lock().unwrap();
+                    let length = l.length();
+                    !Arc::ptr_eq(&l.c0, &c)
+                        && l.occupants.iter().any(|(_, d, _)| length - d < ROUNDABOUT_GAP)
+                });
+                let passing = entry.out_lane.iter().any(|l| {
+                    let l = l.lock().unwrap();
+                    l.occupants.iter().any(|(_, d, _)| *d < ROUNDABOUT_GAP / 2.0)
+                });
+                approaching || passing
+            });
+
+            if !blocked {
+                c_lock.cleared = Some(id);
+            }
+        }
+    }
+
+    // Lowest speed limit of the attached roads
+    fn speed_limit(&self) -> f64 {
+        let mut speed_limit: Option<f64> = None;
+        for c in &self.connections {
+            let c = c.lock().unwrap();
+            for l in c.in_lane.iter().chain(c.out_lane.iter()) {
+                if self.lanes.iter().any(|o| Arc::ptr_eq(l, o)) {
+                    continue;
+                }
+                let limit = l.lock().unwrap().speed_limit;
+                speed_limit = Some(speed_limit.map_or(limit, |s| s.min(limit)));
+            }
+        }
+        speed_limit.unwrap_or(RoadKind::Residential.speed_limit())
+    }
+
+    fn ring_radius(&self) -> f64 {
+        match self.kind {
+            IntersectionKind::Point => 0.0,
+            IntersectionKind::Roundabout { radius } => radius.max(4.0),
+        }
+    }
+
+    fn add_ring_lanes(&mut self) {
+        let speed_limit = self.speed_limit();
+        let radius = self.ring_radius();
+        let ring_connections: Vec<Arc<Mutex<Connection>>> = self
+            .connections
+            .iter()
+            .filter(|c| rides_ring(c.lock().unwrap().lane_kind))
+            .cloned()
+            .collect();
+
+        // One arm per attached road direction
+        let mut arms: Vec<f64> = Vec::new();
+        for c in &ring_connections {
+            let a = c.lock().unwrap().angle;
+            if !arms.iter().any(|arm| (arm - a).abs() < 0.01) {
+                arms.push(a);
+            }
+        }
+        if arms.is_empty() {
+            return;
+        }
+        arms.sort_by(|a0, a1| a0.partial_cmp(a1).unwrap());
+
+        // Spread between the exit and entry point of an arm
+        let mut spread = (4.0 / radius).min(PI / 8.0);
+        for i in 0..arms.len() {
+            let next = if i + 1 < arms.len() { arms[i + 1] } else { arms[0] + PI * 2.0 };
+            if arms.len() > 1 {
+                spread = spread.min((next - arms[i]) / 4.0);
+            }
+        }
+
+        // Traffic goes counter-clockwise, in decreasing angle.
+        // Exits come right before entries.
+        let mut ring: Vec<(f64, Arc<Mutex<Connection>>)> = Vec::new();
+        let mut exits = Vec::new();
+        let mut entries = Vec::new();
+        for arm in &arms {
+            for (a, list) in [(arm + spread, &mut exits), (arm - spread, &mut entries)] {
+                let mut a = a;
+                while a < 0.0 {
+                    a += PI * 2.0;
+                }
+                while a >= PI * 2.0 {
+                    a -= PI * 2.0;
+                }
+                let n = self.center.offset(a, radius);
+                let c = Arc::new(Mutex::new(Connection::new(
+                    n,
+                    ConnectionKind::Ring,
+                    LaneKind::Car,
+                    a - PI / 2.0,
+                    0.0,
+                )));
+                list.push(c.clone());
+                ring.push((a, c));
+            }
+        }
+        ring.sort_by(|(a0, _), (a1, _)| a1.partial_cmp(a0).unwrap());
+
+        // Ring segments
+        for i in 0..ring.len() {
+            let c0 = &ring[i].1;
+            let c1 = &ring[(i + 1) % ring.len()].1;
+            let curve = Curve::new_1(
+                c0.lock().unwrap().center,
+                c1.lock().unwrap().center,
+                self.center,
+                true,
+                true,
+            );
+            let l = Arc::new(Mutex::new(Lane::new(
+                c0.clone(),
+                c1.clone(),
+                curve,
+                4.0,
+                LaneKind::Car,
+                speed_limit,
+            )));
+            c0.lock().unwrap().out_lane.push(l.clone());
+            c1.lock().unwrap().in_lane.push(l.clone());
+            self.lanes.push(l);
+        }
+
+        // Entry and exit lanes, of the kind of the lane they join
+        for c in &ring_connections {
+            let mut c_lock = c.lock().unwrap();
+            let arm = arms
+                .iter()
+                .position(|arm| (arm - c_lock.angle).abs() < 0.01)
+                .unwrap();
+            if c_lock.kind == ConnectionKind::In {
+                let entry = &entries[arm];
+                let mut entry_lock = entry.lock().unwrap();
+                let curve = Curve::new_tangent(c_lock.center, c_lock.angle + PI, entry_lock.center);
+                let l = Arc::new(Mutex::new(Lane::new(
+                    c.clone(),
+                    entry.clone(),
+                    curve,
+                    4.0,
+                    c_lock.lane_kind,
+                    speed_limit,
+                )));
+                c_lock.out_lane.push(l.clone());
+                entry_lock.in_lane.push(l.clone());
+                self.lanes.push(l);
+                // Yield to traffic on the ring
+                c_lock.sign = SignKind::Yield;
+            } else {
+                let exit = &exits[arm];
+                let mut exit_lock = exit.lock().unwrap();
+                let curve = Curve::new_tangent(c_lock.center, c_lock.angle + PI, exit_lock.center).reverse();
+                let l = Arc::new(Mutex::new(Lane::new(
+                    exit.clone(),
+                    c.clone(),
+                    curve,
+                    4.0,
+                    c_lock.lane_kind,
+                    speed_limit,
+                )));
+                exit_lock.out_lane.push(l.clone());
+                c_lock.in_lane.push(l.clone());
+                self.lanes.push(l);
+            }
+        }
+
+        self.ring = ring.into_iter().map(|(_, c)| c).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::road_profile::RoadProfile;
+
+    #[test]
+    fn test_roundabout_lanes() {
+        let i0 = Arc::new(Mutex::new(Intersection::new_roundabout(0.0, 0.0, 12.0)));
+        let i1 = Arc::new(Mutex::new(Intersection::new(50.0, 0.0)));
+        let i2 = Arc::new(Mutex::new(Intersection::new(100.0, 0.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("One tile"),
+            right_lane_kinds: vec![LaneKind::Car],
+            left_lane_kinds: vec![LaneKind::Car],
+        }));
+        Road::new(i0.clone(), i1, i2, road_profile, RoadKind::Residential);
+
+        let i0 = i0.lock().unwrap();
+        // Two ring segments, one entry and one exit
+        assert_eq!(i0.ring.len(), 2);
+        assert_eq!(i0.lanes.len(), 4);
+
+        // Every lane ends where its connection is
+        for lane in &i0.lanes {
+            let lane = lane.lock().unwrap();
+            let end = lane.position_at(lane.length());
+            let c1 = lane.c1.lock().unwrap().center;
+            assert!(end.distance(&c1) < 0.5);
+        }
+    }
+
+    #[test]
+    fn test_roundabout_bike_lanes() {
+        let i0 = Arc::new(Mutex::new(Intersection::new_roundabout(0.0, 0.0, 12.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("Bike lanes"),
+            right_lane_kinds: vec![LaneKind::Car, LaneKind::Bus, LaneKind::Bike],
+            left_lane_kinds: vec![LaneKind::Car, LaneKind::Bus, LaneKind::Bike],
+        }));
+        for x in [100.0, -100.0] {
+            let i1 = Arc::new(Mutex::new(Intersection::new(x / 2.0, 0.0)));
+            let i2 = Arc::new(Mutex::new(Intersection::new(x, 0.0)));
+            Road::new(i0.clone(), i1, i2, road_profile.clone(), RoadKind::Residential);
+        }
+
+        // Everyone goes around the island, and yields on the way in
+        let mut i0 = i0.lock().unwrap();
+        for lane in &i0.lanes {
+            let lane = lane.lock().unwrap();
+            let (k0, k1) = (lane.c0.lock().unwrap().kind, lane.c1.lock().unwrap().kind);
+            assert!(k0 == ConnectionKind::Ring || k1 == ConnectionKind::Ring);
+        }
+        let entries = i0.ring_entries();
+        assert_eq!(entries.len(), 6);
+        assert!(entries.iter().all(|c| c.lock().unwrap().sign == SignKind::Yield));
+
+        i0.set_kind(IntersectionKind::Point);
+        assert!(entries.iter().all(|c| c.lock().unwrap().sign == SignKind::None));
+    }
+
+    #[test]
+    fn test_one_way_road() {
+        let i0 = Arc::new(Mutex::new(Intersection::new(0.0, 0.0)));
+        let i1 = Arc::new(Mutex::new(Intersection::new(50.0, 0.0)));
+        let i2 = Arc::new(Mutex::new(Intersection::new(100.0, 0.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("One way"),
+            right_lane_kinds: vec![LaneKind::Car, LaneKind::Car, LaneKind::Bike],
+            left_lane_kinds: vec![],
+        }));
+        let road = Road::new(i0.clone(), i1, i2.clone(), road_profile, RoadKind::Residential);
+        assert_eq!(road.lanes.len(), 3);
+
+        // Everything leaves i0 and arrives at i2
+        let i0 = i0.lock().unwrap();
+        let i2 = i2.lock().unwrap();
+        assert!(i0.connections.iter().all(|c| c.lock().unwrap().kind == ConnectionKind::Out));
+        assert!(i2.connections.iter().all(|c| c.lock().unwrap().kind == ConnectionKind::In));
+
+        // Lanes keep their side of the road
+        for lane in &road.lanes {
+            let lane = lane.lock().unwrap();
+            let c0 = lane.c0.lock().unwrap();
+            let c1 = lane.c1.lock().unwrap();
+            assert_eq!(c0.offset, c1.offset);
+            assert!(c0.lane_kind == c1.lane_kind);
+        }
+    }
+
+    #[test]
+    fn test_crosswalks() {
+        let i0 = Arc::new(Mutex::new(Intersection::new(0.0, 0.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("Sidewalks"),
+            right_lane_kinds: vec![LaneKind::Car, LaneKind::Pedestrian],
+            left_lane_kinds: vec![LaneKind::Car, LaneKind::Pedestrian],
+        }));
+        for (x, y) in [(100.0, 0.0), (0.0, 100.0)] {
+            let i1 = Arc::new(Mutex::new(Intersection::new(x / 2.0, y / 2.0)));
+            let i2 = Arc::new(Mutex::new(Intersection::new(x, y)));
+            Road::new(i0.clone(), i1, i2, road_profile.clone(), RoadKind::Residential);
+        }
+
+        let mut i0 = i0.lock().unwrap();
+        let sidewalks: Vec<Arc<Mutex<Lane>>> = i0
+            .lanes
+            .iter()
+            .filter(|l| l.lock().unwrap().kind == LaneKind::Pedestrian)
+            .cloned()
+            .collect();
+        // One crosswalk per road and one lane around each corner
+        assert_eq!(i0.crosswalks.len(), 2);
+        assert_eq!(sidewalks.len(), 4);
+
+        // Cars wait while someone is on the crosswalk
+        let crosswalk = i0.crosswalks[0].clone();
+        let angle = crosswalk.lock().unwrap().c0.lock().unwrap().angle;
+        crosswalk.lock().unwrap().enter(0, 1.0, 0.6);
+        i0.update();
+        for c in &i0.connections {
+            let c = c.lock().unwrap();
+            if c.lane_kind == LaneKind::Car {
+                assert_eq!(c.blocked, (c.angle - angle).abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bike_merges() {
+        let i0 = Arc::new(Mutex::new(Intersection::new(0.0, 0.0)));
+        let bike_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("Bike lanes"),
+            right_lane_kinds: vec![LaneKind::Car, LaneKind::Bike],
+            left_lane_kinds: vec![LaneKind::Car, LaneKind::Bike],
+        }));
+        let car_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("Cars"),
+            right_lane_kinds: vec![LaneKind::Car, LaneKind::Car],
+            left_lane_kinds: vec![LaneKind::Car, LaneKind::Car],
+        }));
+        for (x, y, profile) in [(100.0, 0.0, bike_profile), (0.0, 100.0, car_profile)] {
+            let i1 = Arc::new(Mutex::new(Intersection::new(x / 2.0, y / 2.0)));
+            let i2 = Arc::new(Mutex::new(Intersection::new(x, y)));
+            Road::new(i0.clone(), i1, i2, profile, RoadKind::Residential);
+        }
+
+        // One turn lane each way, from and to the outermost car lane
+        let i0 = i0.lock().unwrap();
+        let bike_lanes: Vec<Arc<Mutex<Lane>>> = i0
+            .lanes
+            .iter()
+            .filter(|l| {
+                let l = l.lock().unwrap();
+                l.kind == LaneKind::Bike && l.c0.lock().unwrap().angle != l.c1.lock().unwrap().angle
+            })
+            .cloned()
+            .collect();
+        assert_eq!(bike_lanes.len(), 2);
+        for lane in &bike_lanes {
+            let lane = lane.lock().unwrap();
+            for c in [&lane.c0, &lane.c1] {
+                let c = c.lock().unwrap();
+                assert!(c.lane_kind == LaneKind::Bike || c.offset > 4.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_turnarounds() {
+        let i0 = Arc::new(Mutex::new(Intersection::new(0.0, 0.0)));
+        let i1 = Arc::new(Mutex::new(Intersection::new(50.0, 0.0)));
+        let i2 = Arc::new(Mutex::new(Intersection::new(100.0, 0.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("Bike lane one way"),
+            right_lane_kinds: vec![LaneKind::Car, LaneKind::Bike],
+            left_lane_kinds: vec![LaneKind::Car],
+        }));
+        Road::new(i0, i1, i2.clone(), road_profile, RoadKind::Residential);
+
+        // Everyone arriving at the end of the road can turn around
+        let i2 = i2.lock().unwrap();
+        for c in &i2.connections {
+            let c = c.lock().unwrap();
+            if c.kind == ConnectionKind::In {
+                assert!(!c.out_lane.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_gone_agents_release_signs() {
+        let i0 = Arc::new(Mutex::new(Intersection::new(0.0, 0.0)));
+        let i1 = Arc::new(Mutex::new(Intersection::new(50.0, 0.0)));
+        let i2 = Arc::new(Mutex::new(Intersection::new(100.0, 0.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("One tile"),
+            right_lane_kinds: vec![LaneKind::Car],
+            left_lane_kinds: vec![LaneKind::Car],
+        }));
+        Road::new(i0, i1, i2.clone(), road_profile, RoadKind::Residential);
+
+        let mut i2 = i2.lock().unwrap();
+        i2.set_signs(SignKind::Stop);
+        let c = i2.signable_connections()[0].clone();
+        c.lock().unwrap().waiting = vec![(1, 0), (2, 1)];
+
+        // Agent 1 is let through, but is not on the way in, so agent 2 goes next
+        i2.update();
+        assert_eq!(c.lock().unwrap().cleared, Some(1));
+        i2.update();
+        assert_eq!(c.lock().unwrap().cleared, Some(2));
+        assert_eq!(c.lock().unwrap().waiting, vec![(2, 1)]);
+
+        // Agent 2 left the map
+        i2.forget_agents(&[2]);
+        assert_eq!(c.lock().unwrap().cleared, None);
+        assert!(c.lock().unwrap().waiting.is_empty());
     }
 }
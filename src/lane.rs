@@ -1,22 +1,94 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
 
 use cairo::Context;
 
 use crate::{connection::Connection, curve::Curve, node::Node};
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+// Sideways acceleration agents accept in curves
+const LATERAL_ACCELERATION: f64 = 0.1;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum LaneKind {
     Car,
     Bike,
     Pedestrian,
+    Median,
+    Shoulder,
+    Parking,
+    Bus,
+    Tram,
+}
+
+pub struct LaneKindInfo {
+    pub name: &'static str,
+    pub width: f64,
+    pub color: (f64, f64, f64),
+    // Do agents travel along it?
+    pub traversable: bool,
 }
 
+// Indexed by LaneKind
+const LANE_KIND_INFO: [LaneKindInfo; 8] = [
+    LaneKindInfo { name: "Car", width: 4.0, color: (0.15, 0.13, 0.13), traversable: true },
+    LaneKindInfo { name: "Bike", width: 2.0, color: (0.53, 0.40, 0.38), traversable: true },
+    LaneKindInfo { name: "Pedestrian", width: 2.0, color: (0.33, 0.33, 0.36), traversable: true },
+    LaneKindInfo { name: "Median", width: 2.0, color: (0.62, 0.60, 0.56), traversable: false },
+    LaneKindInfo { name: "Shoulder", width: 2.0, color: (0.25, 0.23, 0.22), traversable: false },
+    LaneKindInfo { name: "Parking", width: 3.0, color: (0.20, 0.18, 0.18), traversable: false },
+    LaneKindInfo { name: "Bus", width: 4.0, color: (0.40, 0.14, 0.12), traversable: true },
+    LaneKindInfo { name: "Tram", width: 4.0, color: (0.35, 0.34, 0.32), traversable: true },
+];
+
+impl LaneKind {
+    pub const ALL: [LaneKind; 8] = [
+        LaneKind::Car,
+        LaneKind::Bike,
+        LaneKind::Pedestrian,
+        LaneKind::Median,
+        LaneKind::Shoulder,
+        LaneKind::Parking,
+        LaneKind::Bus,
+        LaneKind::Tram,
+    ];
+
+    pub fn info(&self) -> &'static LaneKindInfo {
+        &LANE_KIND_INFO[*self as usize]
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.info().name
+    }
+
+    pub fn width(&self) -> f64 {
+        self.info().width
+    }
+
+    pub fn is_traversable(&self) -> bool {
+        self.info().traversable
+    }
+
+    pub fn from_name(name: &str) -> Option<LaneKind> {
+        LaneKind::ALL.iter().copied().find(|k| k.name() == name)
+    }
+}
+
+// Ids handed out to new lanes
+static NEXT_LANE_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub struct Lane {
+    // Unique for the run, kept when a rebuilt road replaces the lane
+    pub id: usize,
     pub c0: Arc<Mutex<Connection>>,
     pub c1: Arc<Mutex<Connection>>,
     pub curve: Curve,
     pub width: f64,
     pub kind: LaneKind,
+    pub speed_limit: f64,
+    // Agents on the lane as (agent id, distance, length)
+    pub occupants: Vec<(usize, f64, f64)>,
 }
 
 impl Lane {
@@ -26,23 +98,23 @@ impl Lane {
         curve: Curve,
         width: f64,
         kind: LaneKind,
+        speed_limit: f64,
     ) -> Self {
         Self {
+            id: NEXT_LANE_ID.fetch_add(1, Ordering::Relaxed),
             c0,
             c1,
             curve,
             width,
             kind,
+            speed_limit,
+            occupants: Vec::new(),
         }
     }
 
     pub fn draw(&self, context: &Context) {
-        let (width, (r, g, b)) = match &self.kind {
-            LaneKind::Car => (4.0, (0.15, 0.13, 0.13)),
-            LaneKind::Bike => (2.0, (0.53, 0.40, 0.38)),
-            LaneKind::Pedestrian => (2.0, (0.33, 0.33, 0.36)),
-        };
-
+        let width = self.kind.width();
+        let (r, g, b) = self.kind.info().color;
 
         // Offset Curves
         let curve1 = self.curve.offset(width / 2.0);
@@ -58,6 +130,48 @@ impl Lane {
         //context.stroke_preserve().expect("omg!");
         context.fill().expect("omg!");
 
+        // Markings, the inner edge is on the left
+        let inner = self.curve.offset(-width / 2.0);
+        let outer = self.curve.offset(width / 2.0);
+        match self.kind {
+            LaneKind::Median => {
+                // Curbs
+                context.set_source_rgb(0.80, 0.78, 0.74);
+                inner.plot(context);
+                context.stroke().expect("omg!");
+                outer.plot(context);
+                context.stroke().expect("omg!");
+            }
+            LaneKind::Shoulder => {
+                // Edge line
+                context.set_source_rgb(0.90, 0.90, 0.90);
+                inner.plot(context);
+                context.stroke().expect("omg!");
+            }
+            LaneKind::Parking => {
+                // Stalls
+                context.set_source_rgb(0.90, 0.90, 0.90);
+                let stalls = (self.length() / 6.0).floor().max(1.0) as usize;
+                for i in 0..=stalls {
+                    let t = i as f64 / stalls as f64;
+                    let n0 = inner.position_at(t * inner.length());
+                    let n1 = outer.position_at(t * outer.length());
+                    context.move_to(n0.x, n0.y);
+                    context.line_to(n1.x, n1.y);
+                }
+                context.stroke().expect("omg!");
+            }
+            LaneKind::Tram => {
+                // Rails
+                context.set_source_rgb(0.70, 0.70, 0.72);
+                self.curve.offset(-0.75).plot(context);
+                context.stroke().expect("omg!");
+                self.curve.offset(0.75).plot(context);
+                context.stroke().expect("omg!");
+            }
+            LaneKind::Car | LaneKind::Bike | LaneKind::Pedestrian | LaneKind::Bus => {}
+        }
+
         // Draw Center Line
         //context.set_source_rgb(0.60, 0.20, 0.60);
         //self.curve.plot(context);
@@ -71,4 +185,107 @@ impl Lane {
     pub fn position_at(&self, d: f64) -> Node {
         self.curve.position_at(d)
     }
+
+    // Speed limit, lowered in tight curves
+    pub fn max_speed(&self) -> f64 {
+        if self.curve.is_curved {
+            let radius = self.curve.c.distance(&self.curve.n0);
+            self.speed_limit.min((LATERAL_ACCELERATION * radius).sqrt())
+        } else {
+            self.speed_limit
+        }
+    }
+
+    // Closest point on the lane to n, as (distance along the lane, distance to n)
+    pub fn closest(&self, n: &Node) -> (f64, f64) {
+        let length = self.length();
+        let mut closest = (0.0, f64::MAX);
+        let mut d = 0.0;
+        while d <= length {
+            let distance = self.position_at(d).distance(n);
+            if distance < closest.1 {
+                closest = (d, distance);
+            }
+            d += 0.5;
+        }
+        closest
+    }
+
+    // Direction of travel at d
+    pub fn heading_at(&self, d: f64) -> f64 {
+        let length = self.length();
+        let d0 = (d - 0.1).max(0.0);
+        let d1 = (d + 0.1).min(length);
+        self.position_at(d0).angle(&self.position_at(d1))
+    }
+
+    pub fn enter(&mut self, id: usize, d: f64, length: f64) {
+        self.occupants.push((id, d, length));
+    }
+
+    pub fn leave(&mut self, id: usize) {
+        self.occupants.retain(|(o, _, _)| *o != id);
+    }
+
+    pub fn move_occupant(&mut self, id: usize, d: f64) {
+        for occupant in &mut self.occupants {
+            if occupant.0 == id {
+                occupant.1 = d;
+            }
+        }
+    }
+
+    // Distance to the rear of the closest agent ahead of d, if any
+    pub fn leader_distance(&self, id: usize, d: f64) -> Option<f64> {
+        self.occupants
+            .iter()
+            .filter(|(o, od, _)| *o != id && (*od > d || (*od == d && *o < id)))
+            .fold(None, |min: Option<(f64, f64)>, (_, od, length)| match min {
+                Some((m, _)) if m <= *od => min,
+                _ => Some((*od, *length)),
+            })
+            .map(|(od, length)| od - length / 2.0)
+    }
+
+    // Can an agent of this length be put at d without overlapping anyone?
+    pub fn has_room(&self, d: f64, length: f64) -> bool {
+        self.occupants
+            .iter()
+            .all(|(_, od, ol)| (od - d).abs() >= (length + ol) / 2.0 + 1.0)
+    }
+
+    pub fn is_occupied(&self) -> bool {
+        !self.occupants.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionKind;
+
+    #[test]
+    fn test_lane_kind_info() {
+        for kind in LaneKind::ALL {
+            assert_eq!(LaneKind::from_name(kind.name()), Some(kind));
+        }
+        assert!(LaneKind::Car.is_traversable());
+        assert!(!LaneKind::Median.is_traversable());
+    }
+
+    #[test]
+    fn test_leader_distance() {
+        let n0 = Node::new(0.0, 0.0);
+        let n1 = Node::new(100.0, 0.0);
+        let c0 = Arc::new(Mutex::new(Connection::new(n0, ConnectionKind::Out, LaneKind::Car, 0.0, 0.0)));
+        let c1 = Arc::new(Mutex::new(Connection::new(n1, ConnectionKind::In, LaneKind::Car, 0.0, 0.0)));
+        let mut lane = Lane::new(c0, c1, Curve::new_tangent(n0, 0.0, n1), 4.0, LaneKind::Car, 1.0);
+
+        lane.enter(0, 50.0, 10.0);
+        lane.enter(1, 20.0, 4.5);
+        // The rear of the truck ahead
+        assert_eq!(lane.leader_distance(1, 20.0), Some(45.0));
+        assert_eq!(lane.leader_distance(0, 50.0), None);
+        assert!((lane.heading_at(20.0)).abs() < 0.01);
+    }
 }
@@ -3,46 +3,226 @@ use std::{
     thread,
 };
 
-use agent::Agent;
 use cairo::glib::{Continue, MainContext, PRIORITY_DEFAULT};
 use gtk4::{
     gdk::Key,
     prelude::{ApplicationExt, ApplicationExtManual, DrawingAreaExtManual},
-    traits::{GestureExt, GestureSingleExt, GtkWindowExt, WidgetExt},
+    traits::{EventControllerExt, GestureExt, GestureSingleExt, GtkWindowExt, WidgetExt},
     Application, ApplicationWindow, DrawingArea,
 };
-use lane::{LaneKind, Lane};
+use agent::AgentKind;
+use lane::{Lane, LaneKind};
+use metrics::{Metrics, BIN_TICKS};
+use node::Node;
+use od::OdMatrix;
+use recording::{Recorder, Replay};
+use render::Region;
 use road::RoadKind;
 use road_profile::RoadProfile;
+use toolbar::Tool;
+use transit::Stop;
 
 extern crate cairo;
 
 mod agent;
 mod connection;
 mod curve;
+mod demand;
+mod geojson;
+mod graph;
+mod inflate;
 mod intersection;
 mod lane;
 mod map;
+mod metrics;
+mod network;
 mod node;
+mod od;
+mod opendrive;
+mod osm;
+mod overlay;
+mod pbf;
+mod profile_editor;
 mod property;
+mod recording;
+mod render;
 mod road;
 mod road_profile;
+mod route;
+mod stats;
+mod sumo;
 mod toolbar;
+mod transit;
+mod validate;
 
-use crate::intersection::Intersection;
+use crate::intersection::{Intersection, IntersectionKind};
 use crate::map::Map;
-use crate::road::Road;
 use crate::toolbar::Toolbar;
 
 const SCALE: f64 = 3.0;
 const TILE: f64 = 8.0;
+const ROUNDABOUT_RADIUS: f64 = 12.0;
+const PROFILES_FILE: &str = "profiles.txt";
+const NETWORK_FILE: &str = "network.txt";
+const GEOJSON_FILE: &str = "network.geojson";
+const IMAGE_FILES: [&str; 3] = ["map.png", "map.svg", "map.pdf"];
+const RECORDING_FILE: &str = "recording.bin";
+const METRICS_FILE: &str = "metrics.csv";
+
+// Export format follows from the file extension. Images show the region,
+// or else the whole map, at scale pixels or points per map unit.
+fn export(map: &Map, path: &str, region: Option<Region>, scale: f64) -> std::io::Result<()> {
+    if path.ends_with(".png") || path.ends_with(".svg") || path.ends_with(".pdf") {
+        render::save(map, path, region, scale)
+    } else if path.ends_with(".csv") || path.ends_with(".parquet") {
+        map.metrics.save(map, path)
+    } else if path.ends_with(".geojson") || path.ends_with(".json") {
+        geojson::save(map, path)
+    } else if path.ends_with(".net.xml") {
+        sumo::save(map, path)
+    } else if path.ends_with(".xodr") {
+        opendrive::save(map, path)
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unknown export format"))
+    }
+}
+
+fn export_images(map: &Map, region: Option<Region>, scale: f64) {
+    for file in IMAGE_FILES.iter() {
+        match export(map, file, region, scale) {
+            Ok(()) => println!("Exported map to {}", file),
+            Err(e) => println!("Failed to export {}: {}", file, e),
+        }
+    }
+}
+
+// Keep the network in the recording up to date with the edits.
+fn record_edit(map: &Arc<Mutex<Map>>, recorder: &Arc<Mutex<Option<Recorder>>>, what: &str) {
+    let map = map.lock().unwrap();
+    if let Some(recorder) = recorder.lock().unwrap().as_mut() {
+        if let Err(e) = recorder.edit(&map, what) {
+            println!("Failed to record edit: {}", e);
+        }
+    }
+}
 
 fn main() {
+    // Our own options, gtk gets the rest
+    let mut od_file: Option<String> = None;
+    let mut network_file: Option<String> = None;
+    let mut osm_file: Option<String> = None;
+    let mut headless_validate = false;
+    let mut export_files: Vec<String> = Vec::new();
+    let mut export_region: Option<Region> = None;
+    let mut export_scale = SCALE;
+    let mut record_file: Option<String> = None;
+    let mut replay_file: Option<String> = None;
+    let mut headless_ticks: u64 = 0;
+    let mut bin_ticks = BIN_TICKS;
+    let mut gtk_args: Vec<String> = Vec::new();
+    let mut args = std::env::args();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--od" => od_file = Some(args.next().expect("--od needs a CSV file")),
+            "--map" => network_file = Some(args.next().expect("--map needs a network file")),
+            "--osm" => osm_file = Some(args.next().expect("--osm needs an .osm or .osm.pbf file")),
+            "--validate" => headless_validate = true,
+            "--export" => export_files.push(args.next().expect("--export needs a file")),
+            "--region" => {
+                let region = args.next().expect("--region needs x,y,width,height");
+                export_region = Some(Region::parse(&region).expect("Bad --region"));
+            }
+            "--scale" => {
+                let scale = args.next().expect("--scale needs pixels per map unit");
+                export_scale = scale.parse().expect("Bad --scale");
+            }
+            "--record" => record_file = Some(args.next().expect("--record needs a file")),
+            "--replay" => replay_file = Some(args.next().expect("--replay needs a recording")),
+            "--ticks" => {
+                let ticks = args.next().expect("--ticks needs a number of ticks");
+                headless_ticks = ticks.parse().expect("Bad --ticks");
+            }
+            "--bin" => {
+                let ticks = args.next().expect("--bin needs a number of ticks");
+                bin_ticks = ticks.parse().expect("Bad --bin");
+            }
+            _ => gtk_args.push(arg),
+        }
+    }
+
+    let load_network = move || -> Map {
+        if let Some(osm_file) = &osm_file {
+            match osm::load(osm_file) {
+                Ok(map) => {
+                    println!("Imported {} roads from {}", map.roads.len(), osm_file);
+                    return map;
+                }
+                Err(e) => println!("Failed to import {}: {}", osm_file, e),
+            }
+        }
+        match &network_file {
+            Some(network_file) => match network::load(network_file) {
+                Ok(map) => map,
+                Err(e) => {
+                    println!("Failed to load network {}: {}", network_file, e);
+                    Map::new()
+                }
+            },
+            None => Map::new(),
+        }
+    };
+    let load_map = move || -> Map {
+        let mut map = load_network();
+        map.metrics = Metrics::new(bin_ticks);
+
+        // Measured demand
+        if let Some(od_file) = &od_file {
+            match OdMatrix::load(od_file) {
+                Ok(od) => {
+                    for warning in map.set_od(od) {
+                        println!("{}", warning);
+                    }
+                }
+                Err(e) => println!("Failed to load OD matrix {}: {}", od_file, e),
+            }
+        }
+        map
+    };
+
+    // Check the network without opening a window
+    if headless_validate {
+        let problems = load_map().validate();
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        println!("{} problems", problems.len());
+        std::process::exit(if problems.is_empty() { 0 } else { 1 });
+    }
+
+    // Write the network in other formats without opening a window, after
+    // simulating for a while when asked to
+    if !export_files.is_empty() {
+        let mut map = load_map();
+        for _ in 0..headless_ticks {
+            map.update();
+        }
+        for file in &export_files {
+            match export(&map, file, export_region, export_scale) {
+                Ok(()) => println!("Exported {}", file),
+                Err(e) => {
+                    println!("Failed to export {}: {}", file, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
     let app = Application::builder()
         .application_id("dev.kval.roads")
         .build();
 
-    app.connect_activate(|app| {
+    app.connect_activate(move |app| {
         if gtk4::init().is_err() {
             panic!("gtk4 failed, blame the developer!");
         }
@@ -54,42 +234,64 @@ fn main() {
             .build();
 
         let drawing_area = DrawingArea::new();
-        let map = Arc::new(Mutex::new(Map::new()));
+        let map = Arc::new(Mutex::new(load_map()));
         let toolbar = Arc::new(Mutex::new(Toolbar::new()));
-        let mut road_profiles: Vec<Arc<Mutex<RoadProfile>>> = Vec::new();
 
-        // Add Basic Road Profiles
-        {
-            // One tile Car only
-            let road_profile = Arc::new(Mutex::new(RoadProfile {
-                right_lane_kinds: vec![LaneKind::Car],
-                left_lane_kinds: vec![LaneKind::Car],
-            }));
-            road_profiles.push(road_profile);
-
-            // Two tile Bike Lanes and Sidewalks
-            let road_profile = Arc::new(Mutex::new(RoadProfile {
-                right_lane_kinds: vec![LaneKind::Car, LaneKind::Bike, LaneKind::Pedestrian],
-                left_lane_kinds: vec![LaneKind::Car, LaneKind::Bike, LaneKind::Pedestrian],
-            }));
-            road_profiles.push(road_profile);
-
-            // Eight lanes
-            let road_profile = Arc::new(Mutex::new(RoadProfile {
-                right_lane_kinds: vec![LaneKind::Car, LaneKind::Car, LaneKind::Car, LaneKind::Car],
-                left_lane_kinds: vec![LaneKind::Car, LaneKind::Car, LaneKind::Car, LaneKind::Car],
-            }));
-            road_profiles.push(road_profile);
+        // Record the run, or play one back instead of simulating
+        let recorder: Arc<Mutex<Option<Recorder>>> = Arc::new(Mutex::new(None));
+        if let Some(record_file) = &record_file {
+            match Recorder::create(record_file, &map.lock().unwrap()) {
+                Ok(r) => *recorder.lock().unwrap() = Some(r),
+                Err(e) => println!("Failed to record to {}: {}", record_file, e),
+            }
         }
+        let replay: Arc<Mutex<Option<Replay>>> = Arc::new(Mutex::new(None));
+        if let Some(replay_file) = &replay_file {
+            match Replay::load(replay_file) {
+                Ok(r) => *replay.lock().unwrap() = Some(r),
+                Err(e) => println!("Failed to load recording {}: {}", replay_file, e),
+            }
+        }
+
+        // Load Road Profiles, or start with the basic ones
+        let road_profiles = match RoadProfile::load_all(PROFILES_FILE) {
+            Ok(profiles) if !profiles.is_empty() => profiles,
+            Ok(_) => RoadProfile::defaults(),
+            Err(e) => {
+                println!("Using default road profiles, {}: {}", PROFILES_FILE, e);
+                RoadProfile::defaults()
+            }
+        };
+        toolbar.lock().unwrap().profile = road_profiles.len() - 1;
+        let road_profiles: Arc<Mutex<Vec<Arc<Mutex<RoadProfile>>>>> = Arc::new(Mutex::new(
+            road_profiles
+                .into_iter()
+                .map(|profile| Arc::new(Mutex::new(profile)))
+                .collect(),
+        ));
 
         // Set Draw Function
         {
             let map = map.clone();
-            drawing_area.set_draw_func(move |_, context, _, _| match map.lock() {
+            let toolbar = toolbar.clone();
+            let replay = replay.clone();
+            drawing_area.set_draw_func(move |_, context, width, height| match map.lock() {
                 Ok(map) => {
+                    if let Some(replay) = replay.lock().unwrap().as_ref() {
+                        replay.draw(context, width as f64, height as f64);
+                        return;
+                    }
+                    let toolbar = toolbar.lock().unwrap();
+                    context.save().expect("omg!");
                     context.scale(SCALE, SCALE);
                     context.set_line_width(1.0 / SCALE);
-                    map.draw(context);
+                    map.draw(context, toolbar.overlay);
+                    toolbar.draw(context);
+                    context.restore().expect("omg!");
+                    if toolbar.show_stats {
+                        map.stats.draw(context, map.agents.len());
+                    }
+                    toolbar.overlay.draw_legend(context, height as f64);
                 }
                 Err(_) => todo!(),
             });
@@ -99,17 +301,66 @@ fn main() {
         {
             let map = map.clone();
             let toolbar = toolbar.clone();
+            let road_profiles = road_profiles.clone();
+            let replay = replay.clone();
             let gesture = gtk4::GestureClick::new();
             gesture.set_button(gtk4::gdk::ffi::GDK_BUTTON_PRIMARY as u32);
             gesture.connect_released(move |gesture: &gtk4::GestureClick, _, x, y| {
                 gesture.set_state(gtk4::EventSequenceState::Claimed);
                 println!("Mouse Button Released! {:.1} {:.1}", x, y);
-                let new_x = (x / SCALE / TILE).round() * TILE;
-                let new_y = (y / SCALE / TILE).round() * TILE;
 
                 let mut map = map.lock().unwrap();
                 let mut toolbar = toolbar.lock().unwrap();
 
+                // Clicks scrub through a replay
+                if let Some(replay) = replay.lock().unwrap().as_mut() {
+                    replay.seek(x / gesture.widget().width() as f64);
+                    return;
+                }
+
+                match toolbar.tool {
+                    Tool::Road => {}
+                    Tool::Sign => {
+                        map.toggle_sign_at(&Node::new(x / SCALE, y / SCALE));
+                        return;
+                    }
+                    Tool::Flip => {
+                        if let Some(road) = map.road_at(&Node::new(x / SCALE, y / SCALE)) {
+                            map.flip_road(&road);
+                        }
+                        return;
+                    }
+                    Tool::Zone => {
+                        map.zone_at(&Node::new(x / SCALE, y / SCALE), toolbar.zone);
+                        return;
+                    }
+                    Tool::Sink => {
+                        map.toggle_sink_at(&Node::new(x / SCALE, y / SCALE));
+                        return;
+                    }
+                    Tool::Region => {
+                        // Export once both corners are picked
+                        let n = Node::new(x / SCALE, y / SCALE);
+                        match toolbar.region_start.take() {
+                            Some(start) => {
+                                let region = Region::from_corners(start.x, start.y, n.x, n.y);
+                                export_images(&map, Some(region), toolbar.export_scale);
+                            }
+                            None => toolbar.region_start = Some(n),
+                        }
+                        return;
+                    }
+                    Tool::Transit => {
+                        if let Some((lane, distance)) = map.lane_at(&Node::new(x / SCALE, y / SCALE)) {
+                            toolbar.stops.push(Stop { lane, distance });
+                        }
+                        return;
+                    }
+                }
+
+                let new_x = (x / SCALE / TILE).round() * TILE;
+                let new_y = (y / SCALE / TILE).round() * TILE;
+
                 // Did we click on an existing Intersection?
                 let result = map.intersections.iter().find(|intersection| {
                     (intersection.lock().unwrap().center.x - new_x).abs() < 10.0
@@ -139,48 +390,18 @@ fn main() {
                 if map.intersections.len() > 1 {
                     match &toolbar.selected {
                         Some(old_intersection) => {
-                            let new_intersection_lock = new_intersection.lock().unwrap();
-                            let old_intersection_lock = old_intersection.lock().unwrap();
-
-                            // add road
-                            let middle_intersection = Arc::new(Mutex::new(Intersection::new(
-                                new_intersection_lock.center.x
-                                    - (new_intersection_lock.center.x
-                                        - old_intersection_lock.center.x)
-                                        / 2.0,
-                                new_intersection_lock.center.y
-                                    - (new_intersection_lock.center.y
-                                        - old_intersection_lock.center.y)
-                                        / 2.0,
-                            )));
-
-                            drop(new_intersection_lock);
-                            drop(old_intersection_lock);
-
-                            let new_road = Arc::new(Mutex::new(Road::new(
+                            let road_profiles = road_profiles.lock().unwrap();
+                            let road_profile = road_profiles
+                                [toolbar.profile.min(road_profiles.len() - 1)]
+                            .clone();
+                            drop(road_profiles);
+
+                            map.add_road(
                                 old_intersection.clone(),
-                                middle_intersection.clone(),
                                 new_intersection.clone(),
-                                road_profiles.last().unwrap().clone(),
-                            )));
-
-                            old_intersection
-                                .lock()
-                                .unwrap()
-                                .roads
-                                .push(new_road.clone());
-                            middle_intersection
-                                .lock()
-                                .unwrap()
-                                .roads
-                                .push(new_road.clone());
-                            new_intersection
-                                .lock()
-                                .unwrap()
-                                .roads
-                                .push(new_road.clone());
-
-                            map.roads.push(new_road);
+                                road_profile,
+                                toolbar.road_kind,
+                            );
                         }
                         None => {}
                     }
@@ -188,36 +409,234 @@ fn main() {
 
                 toolbar.selected = Some(new_intersection.clone());
             });
+            {
+                let map = map.clone();
+                let recorder = recorder.clone();
+                gesture.connect_released(move |_, _, _, _| record_edit(&map, &recorder, "click"));
+            }
             drawing_area.add_controller(&gesture);
         }
 
         // Button Release Handler
         {
             let map = map.clone();
+            let toolbar = toolbar.clone();
+            let road_profiles = road_profiles.clone();
+            let parent = window.clone();
+            let recorder = recorder.clone();
+            let replay = replay.clone();
             let event_controller = gtk4::EventControllerKey::new();
             event_controller.connect_key_released(move |_, key, _, _| match map.lock() {
                 Ok(mut map) => match key {
                     Key::c => {
-                        let agent = Arc::new(Mutex::new(Agent::new(
-                            map.intersections
-                                .first()
-                                .unwrap()
-                                .clone()
-                                .lock()
-                                .unwrap()
-                                .lanes
-                                .first()
-                                .unwrap()
-                                .clone(),
-                            0.2,
-                        )));
-                        map.agents.push(agent);
+                        // Commuters on or off
+                        map.demand.enabled = !map.demand.enabled;
+                    }
+                    Key::h => {
+                        // A heavy truck
+                        if let Some(lane) = map.random_lane(LaneKind::Car) {
+                            map.spawn_agent(AgentKind::Truck, lane, 0.2);
+                        }
+                    }
+                    Key::w => {
+                        // Someone takes a walk
+                        if let Some(lane) = map.random_lane(LaneKind::Pedestrian) {
+                            map.spawn_agent(AgentKind::Pedestrian, lane, 0.2);
+                        }
+                    }
+                    Key::b => {
+                        // A cyclist, on the road when there are no bike lanes
+                        let lane = map
+                            .random_lane(LaneKind::Bike)
+                            .or_else(|| map.random_lane(LaneKind::Car));
+                        if let Some(lane) = lane {
+                            map.spawn_agent(AgentKind::Bike, lane, 0.2);
+                        }
+                    }
+                    Key::r => toolbar.lock().unwrap().set_tool(Tool::Road),
+                    Key::p => {
+                        let profile = toolbar.lock().unwrap().profile;
+                        profile_editor::open(&parent, road_profiles.clone(), profile);
+                    }
+                    Key::_1 | Key::_2 | Key::_3 | Key::_4 | Key::_5 | Key::_6 | Key::_7
+                    | Key::_8 | Key::_9 => {
+                        // Select the road profile for new roads
+                        let profile = key.to_unicode().unwrap().to_digit(10).unwrap() as usize - 1;
+                        if profile < road_profiles.lock().unwrap().len() {
+                            toolbar.lock().unwrap().profile = profile;
+                        }
+                    }
+                    Key::k => {
+                        // Cycle the kind of new roads
+                        let mut toolbar = toolbar.lock().unwrap();
+                        toolbar.road_kind = toolbar.road_kind.next();
+                    }
+                    Key::s => toolbar.lock().unwrap().set_tool(Tool::Sign),
+                    Key::f => toolbar.lock().unwrap().set_tool(Tool::Flip),
+                    Key::t => toolbar.lock().unwrap().set_tool(Tool::Transit),
+                    Key::v => {
+                        // Show the problems with the network, or hide them again
+                        if map.problems.is_empty() {
+                            map.problems = map.validate();
+                            for problem in &map.problems {
+                                println!("{}", problem);
+                            }
+                        } else {
+                            map.problems.clear();
+                        }
+                    }
+                    Key::g => {
+                        // Show the lanes agents can't get out of, or hide them again
+                        if map.trapped.is_empty() {
+                            map.trapped = map.trapped_lanes();
+                            println!("{} trapped lanes", map.trapped.len());
+                        } else {
+                            map.trapped.clear();
+                        }
+                    }
+                    Key::m => {
+                        // Fix the trapped lanes with the turning movements they miss
+                        let added = map.add_missing_movements();
+                        map.trapped = map.trapped_lanes();
+                        println!("Added {} turning movements, {} trapped lanes left", added, map.trapped.len());
+                    }
+                    Key::e => match export(&map, GEOJSON_FILE, None, SCALE) {
+                        Ok(()) => println!("Exported network to {}", GEOJSON_FILE),
+                        Err(e) => println!("Failed to export network: {}", e),
+                    },
+                    Key::n => match network::save(&map, NETWORK_FILE) {
+                        Ok(()) => println!("Saved network to {}", NETWORK_FILE),
+                        Err(e) => println!("Failed to save network: {}", e),
+                    },
+                    Key::x => toolbar.lock().unwrap().set_tool(Tool::Sink),
+                    Key::y => export_images(&map, None, toolbar.lock().unwrap().export_scale),
+                    Key::j => {
+                        // Color lanes by speed, density, volume, or not at all
+                        let mut toolbar = toolbar.lock().unwrap();
+                        toolbar.overlay = toolbar.overlay.next();
+                        println!("Overlay: {}", toolbar.overlay.name());
+                    }
+                    Key::q => match export(&map, METRICS_FILE, None, SCALE) {
+                        Ok(()) => println!("Exported lane metrics to {}", METRICS_FILE),
+                        Err(e) => println!("Failed to export lane metrics: {}", e),
+                    },
+                    Key::l => {
+                        // Region tool, again to change the export resolution
+                        let mut toolbar = toolbar.lock().unwrap();
+                        if toolbar.tool == Tool::Region {
+                            toolbar.next_export_scale();
+                            println!("Export scale: {}", toolbar.export_scale);
+                        } else {
+                            toolbar.set_tool(Tool::Region);
+                        }
+                    }
+                    Key::i => {
+                        // Trip statistics
+                        let mut toolbar = toolbar.lock().unwrap();
+                        toolbar.show_stats = !toolbar.show_stats;
+                    }
+                    Key::z => {
+                        // Zone tool, again to change the zone
+                        let mut toolbar = toolbar.lock().unwrap();
+                        if toolbar.tool == Tool::Zone {
+                            toolbar.zone = toolbar.zone.next();
+                        } else {
+                            toolbar.set_tool(Tool::Zone);
+                        }
+                    }
+                    Key::Return => {
+                        // Finish the transit line
+                        let mut toolbar = toolbar.lock().unwrap();
+                        if toolbar.tool == Tool::Transit {
+                            let stops = std::mem::take(&mut toolbar.stops);
+                            if let Err(e) = map.add_transit_line(stops) {
+                                println!("{}", e);
+                            }
+                        }
+                    }
+                    Key::Escape => toolbar.lock().unwrap().stops.clear(),
+                    Key::a => {
+                        // All-way stop on the selected intersection
+                        if let Some(intersection) = &toolbar.lock().unwrap().selected {
+                            intersection.lock().unwrap().toggle_all_way_stop();
+                        }
+                    }
+                    Key::o => {
+                        // Toggle roundabout on the selected intersection
+                        let selected = toolbar.lock().unwrap().selected.clone();
+                        if let Some(intersection) = selected {
+                            let kind = match intersection.lock().unwrap().kind {
+                                IntersectionKind::Point => {
+                                    IntersectionKind::Roundabout { radius: ROUNDABOUT_RADIUS }
+                                }
+                                IntersectionKind::Roundabout { .. } => IntersectionKind::Point,
+                            };
+                            map.set_intersection_kind(&intersection, kind);
+                        }
+                    }
+                    Key::d => {
+                        // Start recording the run, or stop
+                        let mut recorder = recorder.lock().unwrap();
+                        match recorder.take() {
+                            Some(r) => match r.finish() {
+                                Ok(()) => println!("Recorded to {}", RECORDING_FILE),
+                                Err(e) => println!("Failed to finish recording: {}", e),
+                            },
+                            None => match Recorder::create(RECORDING_FILE, &map) {
+                                Ok(r) => *recorder = Some(r),
+                                Err(e) => println!("Failed to record to {}: {}", RECORDING_FILE, e),
+                            },
+                        }
+                    }
+                    Key::u => {
+                        // Play the recording back, or go back to simulating
+                        let mut replay = replay.lock().unwrap();
+                        if replay.take().is_none() {
+                            match Replay::load(RECORDING_FILE) {
+                                Ok(r) => *replay = Some(r),
+                                Err(e) => println!("Failed to load recording {}: {}", RECORDING_FILE, e),
+                            }
+                        }
+                    }
+                    Key::space => {
+                        if let Some(replay) = replay.lock().unwrap().as_mut() {
+                            replay.paused = !replay.paused;
+                        }
+                    }
+                    Key::Left | Key::Right => {
+                        if let Some(replay) = replay.lock().unwrap().as_mut() {
+                            replay.step(if key == Key::Left { -1 } else { 1 });
+                        }
+                    }
+                    Key::Home | Key::End => {
+                        if let Some(replay) = replay.lock().unwrap().as_mut() {
+                            replay.seek(if key == Key::Home { 0.0 } else { 1.0 });
+                        }
+                    }
+                    Key::plus | Key::minus => {
+                        // Resize the selected roundabout
+                        let selected = toolbar.lock().unwrap().selected.clone();
+                        if let Some(intersection) = selected {
+                            let kind = intersection.lock().unwrap().kind;
+                            if let IntersectionKind::Roundabout { radius } = kind {
+                                let radius = if key == Key::plus { radius + 2.0 } else { radius - 2.0 };
+                                map.set_intersection_kind(
+                                    &intersection,
+                                    IntersectionKind::Roundabout { radius: radius.max(4.0) },
+                                );
+                            }
+                        }
                     }
                     _ => {}
                 },
                 Err(_) => todo!(),
             });
 
+            {
+                let map = map.clone();
+                let recorder = recorder.clone();
+                event_controller.connect_key_released(move |_, _, _, _| record_edit(&map, &recorder, "key"));
+            }
             window.add_controller(&event_controller);
         }
 
@@ -231,11 +650,23 @@ fn main() {
             let _loop_thread = thread::spawn(move || {
                 let duration = std::time::Duration::from_millis(10);
                 loop {
-                    let map = map.lock().unwrap();
-                    for agent in &map.agents {
-                        agent.lock().unwrap().update();
+                    // A replay stands in for the simulation
+                    let replaying = match replay.lock().unwrap().as_mut() {
+                        Some(replay) => {
+                            replay.update();
+                            true
+                        }
+                        None => false,
+                    };
+                    if !replaying {
+                        let mut map = map.lock().unwrap();
+                        map.update();
+                        if let Some(recorder) = recorder.lock().unwrap().as_mut() {
+                            if let Err(e) = recorder.tick(&map) {
+                                println!("Failed to record tick: {}", e);
+                            }
+                        }
                     }
-                    drop(map);
                     thread::sleep(duration);
                     sender.send(true).expect("Failed, blame the developer.");
                 }
@@ -246,5 +677,5 @@ fn main() {
             });
         }
     });
-    app.run();
+    app.run_with_args(&gtk_args);
 }
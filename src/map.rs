@@ -1,13 +1,51 @@
 use std::sync::{Arc, Mutex};
 
 use cairo::Context;
+use rand::seq::SliceRandom;
 
-use crate::{agent::Agent, intersection::Intersection, road::Road, TILE};
+use crate::{
+    agent::{Agent, AgentKind, Step}, connection::{Connection, ConnectionKind}, demand::{Access, Demand}, graph, intersection::{Intersection, IntersectionKind},
+    lane::{Lane, LaneKind}, metrics::{Metrics, BIN_TICKS}, node::Node, overlay::Overlay, od::OdMatrix, osm::GeoReference, property::PropertyKind, road::{Road, RoadKind}, road_profile::RoadProfile, route::find_route, stats::{Statistics, TripRecord},
+    transit::{Stop, TransitLine}, validate::{self, Problem}, TILE,
+};
+
+const LINE_COLORS: [(f64, f64, f64); 6] = [
+    (0.90, 0.20, 0.20),
+    (0.20, 0.50, 0.90),
+    (0.95, 0.65, 0.10),
+    (0.60, 0.25, 0.75),
+    (0.10, 0.70, 0.45),
+    (0.90, 0.40, 0.70),
+];
+// Grass under the roads
+pub const BACKGROUND: (f64, f64, f64) = (0.36, 0.55, 0.35);
+// Ticks between transit departures
+const HEADWAY: u64 = 1000;
+// Ticks spent at a transit stop
+const DWELL: u32 = 100;
+
+// Identity of a shared item, to key maps and sets by.
+pub fn key<T>(item: &Arc<Mutex<T>>) -> usize {
+    Arc::as_ptr(item) as usize
+}
 
 pub struct Map {
     pub intersections: Vec<Arc<Mutex<Intersection>>>,
     pub roads: Vec<Arc<Mutex<Road>>>,
     pub agents: Vec<Arc<Mutex<Agent>>>,
+    pub transit_lines: Vec<TransitLine>,
+    pub demand: Demand,
+    pub od: Option<OdMatrix>,
+    pub stats: Statistics,
+    pub metrics: Metrics,
+    // Found by the last validation, highlighted on the map
+    pub problems: Vec<Problem>,
+    // Lanes agents can't get out of, highlighted on the map
+    pub trapped: Vec<Arc<Mutex<Lane>>>,
+    // Set for maps imported from OpenStreetMap
+    pub geo: Option<GeoReference>,
+    pub tick: u64,
+    next_agent_id: usize,
 }
 
 impl Map {
@@ -16,11 +54,605 @@ impl Map {
             intersections: Vec::new(),
             roads: Vec::new(),
             agents: Vec::new(),
+            transit_lines: Vec::new(),
+            demand: Demand::new(),
+            od: None,
+            stats: Statistics::new(),
+            metrics: Metrics::new(BIN_TICKS),
+            problems: Vec::new(),
+            trapped: Vec::new(),
+            geo: None,
+            tick: 0,
+            next_agent_id: 0,
+        }
+    }
+
+    pub fn spawn_agent(
+        &mut self,
+        kind: AgentKind,
+        l: Arc<Mutex<Lane>>,
+        distance: f64,
+    ) -> Arc<Mutex<Agent>> {
+        let mut agent = Agent::new(self.next_agent_id, kind, l, distance);
+        agent.departure = self.tick;
+        let agent = Arc::new(Mutex::new(agent));
+        self.next_agent_id += 1;
+        self.agents.push(agent.clone());
+        agent
+    }
+
+    // Drive from one place to another, if there is a way and room to start.
+    pub fn spawn_trip(&mut self, kind: AgentKind, from: &Access, to: &Access) -> Option<Arc<Mutex<Agent>>> {
+        if !from.0.lock().unwrap().has_room(from.1, kind.length()) {
+            return None;
+        }
+        let route = if Arc::ptr_eq(&from.0, &to.0) && from.1 < to.1 {
+            vec![from.0.clone()]
+        } else {
+            find_route(&from.0, &to.0, kind)?
+        };
+
+        let agent = self.spawn_agent(kind, from.0.clone(), from.1);
+        let mut agent_lock = agent.lock().unwrap();
+        agent_lock.route = route.into_iter().skip(1).collect();
+        agent_lock.destination = Some(to.clone());
+        drop(agent_lock);
+        Some(agent)
+    }
+
+    // Where commuters live and work.
+    pub fn commute_endpoints(&self) -> (Vec<Access>, Vec<Access>) {
+        let mut homes = Vec::new();
+        let mut work = Vec::new();
+        for road in &self.roads {
+            for property in &road.lock().unwrap().properties {
+                if let Some(access) = &property.access {
+                    if property.kind == PropertyKind::Residential {
+                        homes.push(access.clone());
+                    } else if property.kind.is_destination() {
+                        work.push(access.clone());
+                    }
+                }
+            }
+        }
+        (homes, work)
+    }
+
+    // Start the trips people make between zoned properties.
+    fn generate_trips(&mut self) {
+        let (homes, work) = self.commute_endpoints();
+        for (from, to) in self.demand.trips(self.tick, &homes, &work) {
+            self.spawn_trip(AgentKind::Car, &from, &to);
+        }
+    }
+
+    // Where trips from and to a place in the OD matrix start and end.
+    pub fn od_endpoints(&self, name: &str) -> (Vec<Access>, Vec<Access>) {
+        let mut origins = Vec::new();
+        let mut destinations = Vec::new();
+
+        let zone = PropertyKind::from_name(name).filter(|k| k.is_destination() || *k == PropertyKind::Residential);
+        if let Some(zone) = zone {
+            for road in &self.roads {
+                for property in &road.lock().unwrap().properties {
+                    match &property.access {
+                        Some(access) if property.kind == zone => {
+                            origins.push(access.clone());
+                            destinations.push(access.clone());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            return (origins, destinations);
+        }
+
+        let intersection = name
+            .strip_prefix('I')
+            .and_then(|i| i.parse::<usize>().ok())
+            .and_then(|i| self.intersections.get(i));
+        if let Some(intersection) = intersection {
+            for c in &intersection.lock().unwrap().connections {
+                let c = c.lock().unwrap();
+                if c.lane_kind != LaneKind::Car {
+                    continue;
+                }
+                match c.kind {
+                    ConnectionKind::Out => origins.extend(c.out_lane.iter().map(|l| (l.clone(), 0.0))),
+                    ConnectionKind::In => destinations.extend(c.in_lane.iter().map(|l| {
+                        let length = l.lock().unwrap().length();
+                        (l.clone(), length)
+                    })),
+                    ConnectionKind::Ring => {}
+                }
+            }
+        }
+        (origins, destinations)
+    }
+
+    // Use the OD matrix, with a warning for each place trips can't start or
+    // end at.
+    pub fn set_od(&mut self, od: OdMatrix) -> Vec<String> {
+        let mut warnings: Vec<String> = Vec::new();
+        for pair in &od.pairs {
+            let warning = if self.od_endpoints(&pair.origin).0.is_empty() {
+                format!("No way out of {}, trips from it to {} won't start", pair.origin, pair.destination)
+            } else if self.od_endpoints(&pair.destination).1.is_empty() {
+                format!("No way into {}, trips to it from {} won't start", pair.destination, pair.origin)
+            } else {
+                continue;
+            };
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+        self.od = Some(od);
+        warnings
+    }
+
+    // Start the trips of the OD matrix that are due.
+    fn generate_od_trips(&mut self) {
+        let arrivals = match &mut self.od {
+            Some(od) => od.arrivals(self.tick),
+            None => return,
+        };
+
+        let mut rng = rand::thread_rng();
+        for (origin, destination) in arrivals {
+            let (origins, _) = self.od_endpoints(&origin);
+            let (_, destinations) = self.od_endpoints(&destination);
+            let (from, to) = match (origins.choose(&mut rng), destinations.choose(&mut rng)) {
+                (Some(from), Some(to)) => (from.clone(), to.clone()),
+                // Warned about when the matrix was set
+                _ => continue,
+            };
+            self.spawn_trip(AgentKind::Car, &from, &to);
+        }
+    }
+
+    // Zone the property under n.
+    pub fn zone_at(&mut self, n: &Node, kind: PropertyKind) -> bool {
+        for road in &self.roads {
+            for property in &mut road.lock().unwrap().properties {
+                if property.contains(n) {
+                    property.kind = kind;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn add_transit_line(&mut self, stops: Vec<Stop>) -> Result<(), String> {
+        let kind = match stops.first() {
+            Some(stop) if stop.lane.lock().unwrap().kind == LaneKind::Tram => AgentKind::Tram,
+            _ => AgentKind::Bus,
+        };
+        let number = self.transit_lines.len();
+        let line = TransitLine::new(
+            format!("Line {}", number + 1),
+            LINE_COLORS[number % LINE_COLORS.len()],
+            kind,
+            stops,
+            HEADWAY,
+            DWELL,
+        )?;
+        self.transit_lines.push(line);
+        Ok(())
+    }
+
+    // Send out the transit vehicles that are due.
+    fn depart_transit(&mut self) {
+        let mut departures = Vec::new();
+        for line in &mut self.transit_lines {
+            if line.is_due(self.tick) {
+                line.last_departure = Some(self.tick);
+                departures.push((
+                    line.kind,
+                    line.path.clone(),
+                    line.stops.clone(),
+                    line.dwell,
+                    line.color,
+                ));
+            }
+        }
+
+        for (kind, path, stops, dwell, color) in departures {
+            let agent = self.spawn_agent(kind, path[0].clone(), stops[0].distance);
+            let mut agent = agent.lock().unwrap();
+            agent.route = path.into_iter().skip(1).collect();
+            // Out of service after the last stop
+            agent.destination = stops.last().map(|stop| (stop.lane.clone(), stop.distance));
+            agent.stops = stops.into_iter().collect();
+            agent.dwell = dwell;
+            agent.color = color;
+        }
+    }
+
+    pub fn update(&mut self) {
+        self.tick += 1;
+        self.depart_transit();
+        self.generate_trips();
+        self.generate_od_trips();
+        for intersection in &self.intersections {
+            intersection.lock().unwrap().update();
+        }
+        let mut finished = Vec::new();
+        for agent in &self.agents {
+            let mut agent = agent.lock().unwrap();
+            let step = match agent.update(self.tick) {
+                Ok(step) => step,
+                Err(e) => {
+                    println!("Agent {} left the map: {}", agent.id, e);
+                    Step::Sunk
+                }
+            };
+            if step != Step::Moving {
+                finished.push(agent.id);
+                self.stats.record(TripRecord {
+                    agent_id: agent.id,
+                    kind: agent.kind,
+                    departure: agent.departure,
+                    arrival: self.tick,
+                    distance: agent.travelled,
+                    free_flow_time: agent.free_flow_time,
+                    completed: step == Step::Arrived,
+                });
+            }
+        }
+        self.agents.retain(|agent| !finished.contains(&agent.lock().unwrap().id));
+        self.forget_agents(&finished);
+        self.metrics.record(self.tick, &self.agents);
+    }
+
+    // Agents no longer waiting at any sign
+    fn forget_agents(&self, ids: &[usize]) {
+        if ids.is_empty() {
+            return;
+        }
+        for intersection in &self.intersections {
+            intersection.lock().unwrap().forget_agents(ids);
+        }
+    }
+
+    // Road lanes, then intersection lanes, in the same order for a map and
+    // the map loaded from its saved network.
+    pub fn lanes(&self) -> Vec<Arc<Mutex<Lane>>> {
+        let mut lanes = Vec::new();
+        for road in &self.roads {
+            lanes.extend(road.lock().unwrap().lanes.iter().cloned());
+        }
+        for intersection in &self.intersections {
+            lanes.extend(intersection.lock().unwrap().lanes.iter().cloned());
+        }
+        lanes
+    }
+
+    pub fn random_lane(&self, kind: LaneKind) -> Option<Arc<Mutex<Lane>>> {
+        let lanes: Vec<Arc<Mutex<Lane>>> = self
+            .roads
+            .iter()
+            .flat_map(|road| road.lock().unwrap().lanes.clone())
+            .filter(|lane| lane.lock().unwrap().kind == kind)
+            .collect();
+        lanes.choose(&mut rand::thread_rng()).cloned()
+    }
+
+    // Road from i0 to i2, bending through a new intersection halfway.
+    pub fn add_road(
+        &mut self,
+        i0: Arc<Mutex<Intersection>>,
+        i2: Arc<Mutex<Intersection>>,
+        profile: Arc<Mutex<RoadProfile>>,
+        kind: RoadKind,
+    ) -> Arc<Mutex<Road>> {
+        let n0 = i0.lock().unwrap().center;
+        let n2 = i2.lock().unwrap().center;
+        let i1 = Arc::new(Mutex::new(Intersection::new(
+            n2.x - (n2.x - n0.x) / 2.0,
+            n2.y - (n2.y - n0.y) / 2.0,
+        )));
+
+        let road = Arc::new(Mutex::new(Road::new(i0.clone(), i1.clone(), i2.clone(), profile, kind)));
+        i0.lock().unwrap().roads.push(road.clone());
+        i1.lock().unwrap().roads.push(road.clone());
+        i2.lock().unwrap().roads.push(road.clone());
+        self.roads.push(road.clone());
+        road
+    }
+
+    // Lane under n and how far along it n is.
+    pub fn lane_at(&self, n: &Node) -> Option<(Arc<Mutex<Lane>>, f64)> {
+        let mut closest: Option<(Arc<Mutex<Lane>>, f64, f64)> = None;
+        for road in &self.roads {
+            for lane in &road.lock().unwrap().lanes {
+                let lane_lock = lane.lock().unwrap();
+                let (d, distance) = lane_lock.closest(n);
+                let is_closer = match &closest {
+                    Some((_, _, c)) => distance < *c,
+                    None => true,
+                };
+                if distance <= lane_lock.kind.width() / 2.0 && is_closer {
+                    closest = Some((lane.clone(), d, distance));
+                }
+            }
+        }
+        closest.map(|(lane, d, _)| (lane, d))
+    }
+
+    pub fn road_at(&self, n: &Node) -> Option<Arc<Mutex<Road>>> {
+        self.roads
+            .iter()
+            .find(|r| r.lock().unwrap().contains(n))
+            .cloned()
+    }
+
+    // Rebuild the road with its lanes going the other way.
+    pub fn flip_road(&mut self, road: &Arc<Mutex<Road>>) {
+        self.rebuild_road(road, true);
+    }
+
+    // Change the kind of the intersection and build its roads again, so they
+    // meet a roundabout outside of its ring.
+    pub fn set_intersection_kind(&mut self, intersection: &Arc<Mutex<Intersection>>, kind: IntersectionKind) {
+        let roads = {
+            let mut intersection = intersection.lock().unwrap();
+            intersection.set_kind(kind);
+            intersection.roads.clone()
+        };
+        for road in roads {
+            if self.roads.iter().any(|r| Arc::ptr_eq(r, &road)) {
+                self.rebuild_road(&road, false);
+            }
+        }
+    }
+
+    // Build the road again, flipped or fitted to its intersections after a
+    // change. Zoning, signs, sinks, turning movements and agents move over to
+    // the new road where they still fit.
+    fn rebuild_road(&mut self, road: &Arc<Mutex<Road>>, flip: bool) -> Arc<Mutex<Road>> {
+        let road_lock = road.lock().unwrap();
+        let i0 = road_lock.i0.clone();
+        let i1 = road_lock.i1.clone();
+        let i2 = road_lock.i2.clone();
+        let profile = match flip {
+            true => road_lock.profile.flipped(),
+            false => road_lock.profile.clone(),
+        };
+        let kind = road_lock.kind;
+        let connections = road_lock.connections.clone();
+        let places: Vec<_> = connections.iter().map(|c| road_lock.connection_place(c)).collect();
+        let mut old_lanes = road_lock.lanes.clone();
+        let zoning: Vec<(Node, PropertyKind)> = road_lock
+            .properties
+            .iter()
+            .filter(|p| p.kind != PropertyKind::Vacant)
+            .map(|p| (p.center(), p.kind))
+            .collect();
+        drop(road_lock);
+
+        // Everything through the old connections goes away with them
+        let is_old = |c: &Arc<Mutex<Connection>>| connections.iter().any(|o| Arc::ptr_eq(o, c));
+        let mut movements = Vec::new();
+        for intersection in [&i0, &i2] {
+            let intersection_lock = intersection.lock().unwrap();
+            old_lanes.extend(intersection_lock.lanes.iter().cloned());
+            old_lanes.extend(intersection_lock.crosswalks.iter().cloned());
+            for (c0, c1, lane_kind) in &intersection_lock.movements {
+                if is_old(c0) || is_old(c1) {
+                    movements.push((intersection.clone(), c0.clone(), c1.clone(), *lane_kind));
+                }
+            }
+        }
+
+        for intersection in [&i0, &i1, &i2] {
+            intersection.lock().unwrap().remove_road(road, &connections);
+        }
+        let new_road = Arc::new(Mutex::new(Road::new(
+            i0.clone(),
+            i1.clone(),
+            i2.clone(),
+            Arc::new(Mutex::new(profile)),
+            kind,
+        )));
+        for intersection in [&i0, &i1, &i2] {
+            intersection.lock().unwrap().roads.push(new_road.clone());
+        }
+        for r in &mut self.roads {
+            if Arc::ptr_eq(r, road) {
+                *r = new_road.clone();
+            }
+        }
+
+        // Plots stay in place, give each zone to the plot now closest to it
+        for (center, kind) in zoning {
+            let mut new_road = new_road.lock().unwrap();
+            let closest = new_road
+                .properties
+                .iter_mut()
+                .min_by(|p0, p1| p0.center().distance(&center).partial_cmp(&p1.center().distance(&center)).unwrap());
+            if let Some(property) = closest {
+                property.kind = kind;
+            }
+        }
+
+        // Old connections with the new ones at the same place on the road
+        let renamed: Vec<_> = {
+            let new_road = new_road.lock().unwrap();
+            connections
+                .iter()
+                .zip(places)
+                .filter_map(|(old, place)| {
+                    let (end, kind, lane) = place?;
+                    let new = new_road.connection(end, kind, lane)?;
+                    let same_kind = old.lock().unwrap().lane_kind == new.lock().unwrap().lane_kind;
+                    Some((old.clone(), new)).filter(|_| same_kind)
+                })
+                .collect()
+        };
+        for (old, new) in &renamed {
+            let old = old.lock().unwrap();
+            let mut new = new.lock().unwrap();
+            new.sign = old.sign;
+            new.sink = old.sink;
+        }
+        let rename = |c: &Arc<Mutex<Connection>>| match renamed.iter().find(|(old, _)| Arc::ptr_eq(old, c)) {
+            Some((_, new)) => new.clone(),
+            None => c.clone(),
+        };
+        for (intersection, c0, c1, lane_kind) in movements {
+            let (c0, c1) = (rename(&c0), rename(&c1));
+            let fits = [&c0, &c1].iter().all(|c| !is_old(c) && c.lock().unwrap().lane_kind.is_traversable());
+            if fits {
+                intersection.lock().unwrap().add_movement(c0, c1, lane_kind);
+            }
+        }
+
+        // Agents go on the new lane between the same connections
+        let mut new_lanes = new_road.lock().unwrap().lanes.clone();
+        for intersection in [&i0, &i2] {
+            let intersection = intersection.lock().unwrap();
+            new_lanes.extend(intersection.lanes.iter().cloned());
+            new_lanes.extend(intersection.crosswalks.iter().cloned());
+        }
+        let move_lane = |lane: &Arc<Mutex<Lane>>| -> Option<Arc<Mutex<Lane>>> {
+            if !old_lanes.iter().any(|l| Arc::ptr_eq(l, lane)) {
+                return Some(lane.clone());
+            }
+            let (c0, c1, kind) = {
+                let lane = lane.lock().unwrap();
+                (rename(&lane.c0), rename(&lane.c1), lane.kind)
+            };
+            new_lanes
+                .iter()
+                .find(|l| {
+                    let l = l.lock().unwrap();
+                    Arc::ptr_eq(&l.c0, &c0) && Arc::ptr_eq(&l.c1, &c1) && l.kind == kind
+                })
+                .cloned()
+        };
+        // Lanes between the same connections keep their ids, and their counts
+        for old in &old_lanes {
+            if let Some(new) = move_lane(old) {
+                new.lock().unwrap().id = old.lock().unwrap().id;
+            }
+        }
+        let mut aborted = Vec::new();
+        for agent in &self.agents {
+            let mut agent = agent.lock().unwrap();
+            let l = move_lane(&agent.l);
+            let route: Option<Vec<_>> = agent.route.iter().map(&move_lane).collect();
+            let destination = match &agent.destination {
+                Some((lane, d)) => move_lane(lane).map(|lane| Some((lane, *d))),
+                None => Some(None),
+            };
+            let stops: Option<Vec<_>> = agent
+                .stops
+                .iter()
+                .map(|stop| move_lane(&stop.lane).map(|lane| Stop { lane, distance: stop.distance }))
+                .collect();
+            let (l, route, destination, stops) = match (l, route, destination, stops) {
+                (Some(l), Some(route), Some(destination), Some(stops)) => (l, route, destination, stops),
+                _ => {
+                    // Nowhere to go on the new road
+                    agent.l.lock().unwrap().leave(agent.id);
+                    aborted.push(agent.id);
+                    self.stats.record(TripRecord {
+                        agent_id: agent.id,
+                        kind: agent.kind,
+                        departure: agent.departure,
+                        arrival: self.tick,
+                        distance: agent.travelled,
+                        free_flow_time: agent.free_flow_time,
+                        completed: false,
+                    });
+                    continue;
+                }
+            };
+            if !Arc::ptr_eq(&l, &agent.l) {
+                let old_length = agent.l.lock().unwrap().length();
+                agent.l.lock().unwrap().leave(agent.id);
+                let mut l_lock = l.lock().unwrap();
+                agent.distance *= l_lock.length() / old_length.max(0.01);
+                l_lock.enter(agent.id, agent.distance, agent.kind.length());
+                drop(l_lock);
+                agent.l = l;
+            }
+            agent.route = route.into_iter().collect();
+            agent.destination = destination;
+            agent.stops = stops.into_iter().collect();
+        }
+        self.agents.retain(|agent| !aborted.contains(&agent.lock().unwrap().id));
+        self.forget_agents(&aborted);
+
+        // Transit lines stop at the same places and find their way again
+        let lines = std::mem::take(&mut self.transit_lines);
+        for line in lines {
+            let stops: Option<Vec<_>> = line
+                .stops
+                .iter()
+                .map(|stop| move_lane(&stop.lane).map(|lane| Stop { lane, distance: stop.distance }))
+                .collect();
+            let last_departure = line.last_departure;
+            let rerouted = stops
+                .ok_or(format!("{} lost a stop", line.name))
+                .and_then(|stops| TransitLine::new(line.name, line.color, line.kind, stops, line.headway, line.dwell));
+            match rerouted {
+                Ok(mut rerouted) => {
+                    rerouted.last_departure = last_departure;
+                    self.transit_lines.push(rerouted);
+                }
+                Err(e) => println!("Transit line removed: {}", e),
+            }
+        }
+        new_road
+    }
+
+    pub fn intersection_at(&self, n: &Node, max_distance: f64) -> Option<Arc<Mutex<Intersection>>> {
+        self.intersections
+            .iter()
+            .find(|i| i.lock().unwrap().center.distance(n) < max_distance)
+            .cloned()
+    }
+
+    // Cycle the sign of the connection under n, or toggle an all-way stop
+    // when clicking the intersection center.
+    pub fn toggle_sign_at(&mut self, n: &Node) {
+        for intersection in &self.intersections {
+            if intersection.lock().unwrap().cycle_sign_at(n, 3.0) {
+                return;
+            }
+        }
+
+        if let Some(intersection) = self.intersection_at(n, 5.0) {
+            intersection.lock().unwrap().toggle_all_way_stop();
         }
     }
 
-    pub fn draw(&self, context: &Context) {
-        context.set_source_rgb(0.36, 0.55, 0.35);
+    pub fn validate(&self) -> Vec<Problem> {
+        validate::validate(self)
+    }
+
+    pub fn trapped_lanes(&self) -> Vec<Arc<Mutex<Lane>>> {
+        graph::trapped_lanes(&validate::all_lanes(self))
+    }
+
+    pub fn add_missing_movements(&mut self) -> usize {
+        graph::add_missing_movements(self)
+    }
+
+    pub fn toggle_sink_at(&mut self, n: &Node) {
+        for intersection in &self.intersections {
+            if intersection.lock().unwrap().toggle_sink_at(n, 3.0) {
+                return;
+            }
+        }
+    }
+
+    // Draw the map with lanes colored by the overlay.
+    pub fn draw(&self, context: &Context, overlay: Overlay) {
+        let (r, g, b) = BACKGROUND;
+        context.set_source_rgb(r, g, b);
         context.paint().expect("omg!");
 
         // Draw temp grid, slow af
@@ -44,8 +676,167 @@ impl Map {
             intersection.lock().unwrap().draw(context);
         }
 
+        overlay.draw(self, context);
+
+        for line in &self.transit_lines {
+            line.draw(context);
+        }
+
         for agent in &self.agents {
             agent.lock().unwrap().draw(context);
         }
+
+        context.set_source_rgba(0.95, 0.55, 0.10, 0.8);
+        for lane in &self.trapped {
+            context.new_path();
+            lane.lock().unwrap().curve.plot(context);
+            context.stroke().expect("Failed to draw trapped lane!");
+        }
+
+        for problem in &self.problems {
+            problem.draw(context);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connection::SignKind, network};
+
+    #[test]
+    fn test_flip_road() {
+        let mut map = network::parse(
+            "intersection 16 16\nintersection 112 16\nroad 0 1 Residential Two tile: Car Bike | Car Pedestrian\n",
+        )
+        .unwrap();
+        let road = map.roads[0].clone();
+        let (car_lane, bike_lane) = {
+            let road = road.lock().unwrap();
+            road.connection(1, ConnectionKind::In, 0).unwrap().lock().unwrap().sign = SignKind::Stop;
+            road.connection(0, ConnectionKind::In, 0).unwrap().lock().unwrap().sink = true;
+            (road.lanes[0].clone(), road.lanes[1].clone())
+        };
+        let car_lane_id = car_lane.lock().unwrap().id;
+        let car = map.spawn_agent(AgentKind::Car, car_lane, 20.0);
+        map.spawn_agent(AgentKind::Bike, bike_lane, 20.0);
+
+        map.flip_road(&road);
+        let road = map.roads[0].lock().unwrap();
+        assert!(road.connection(1, ConnectionKind::In, 0).unwrap().lock().unwrap().sign == SignKind::Stop);
+        assert!(road.connection(0, ConnectionKind::In, 0).unwrap().lock().unwrap().sink);
+
+        // The car keeps going on the car lane of the new road, the bike lane is
+        // a sidewalk now so the cyclist is taken off the map
+        assert_eq!(map.agents.len(), 1);
+        let car = car.lock().unwrap();
+        assert!(Arc::ptr_eq(&car.l, &road.lanes[0]));
+        assert_eq!(road.lanes[0].lock().unwrap().id, car_lane_id);
+        assert_eq!(road.lanes[0].lock().unwrap().occupants.len(), 1);
+        assert_eq!(map.stats.trips.len(), 1);
+        assert!(!map.stats.trips[0].completed);
+    }
+
+    #[test]
+    fn test_flip_transit() {
+        let mut map = network::parse(
+            "intersection 16 16\nintersection 112 16\nintersection 208 16\n\
+             road 0 1 Residential One tile: Car | Car\nroad 1 2 Residential One way: Car |\n",
+        )
+        .unwrap();
+        let stops: Vec<Stop> = map
+            .roads
+            .iter()
+            .map(|road| Stop { lane: road.lock().unwrap().lanes[0].clone(), distance: 10.0 })
+            .collect();
+        map.add_transit_line(stops).unwrap();
+
+        // The line runs over the new lanes
+        map.flip_road(&map.roads[0].clone());
+        let lanes = map.lanes();
+        assert_eq!(map.transit_lines.len(), 1);
+        for lane in map.transit_lines[0].path.iter().chain(map.transit_lines[0].stops.iter().map(|s| &s.lane)) {
+            assert!(lanes.iter().any(|l| Arc::ptr_eq(l, lane)));
+        }
+
+        // The last stop is on the way back now
+        map.flip_road(&map.roads[1].clone());
+        assert!(map.transit_lines.is_empty());
+    }
+
+    #[test]
+    fn test_flip_zoning() {
+        let mut map = network::parse(
+            "intersection 16 16\n\
+             intersection 112 48\n\
+             road 0 1 Residential Two tile: Car Bike | Car Pedestrian Parking\n\
+             zone 0 0 Residential\n\
+             zone 0 3 Commercial\n",
+        )
+        .unwrap();
+        let (homes, work) = map.commute_endpoints();
+        assert!(!homes.is_empty() && !work.is_empty());
+        let centers: Vec<Node> = map.roads[0].lock().unwrap().properties.iter().map(|p| p.center()).collect();
+
+        map.flip_road(&map.roads[0].clone());
+        let road = map.roads[0].lock().unwrap();
+        let kinds: Vec<PropertyKind> = road.properties.iter().map(|p| p.kind).collect();
+        assert_eq!(
+            kinds,
+            [PropertyKind::Residential, PropertyKind::Vacant, PropertyKind::Vacant, PropertyKind::Commercial]
+        );
+        // Only the road got narrower on one side, the plots are where they were
+        for (property, center) in road.properties.iter().zip(&centers) {
+            assert!(property.center().distance(center) < 4.0);
+        }
+        drop(road);
+        let (new_homes, new_work) = map.commute_endpoints();
+        assert_eq!((new_homes.len(), new_work.len()), (homes.len(), work.len()));
+    }
+
+    #[test]
+    fn test_grow_roundabout() {
+        let mut map = network::parse(
+            "intersection 16 64\nintersection 128 64\nintersection 240 64\n\
+             road 0 1 Residential One tile: Car | Car\nroad 1 2 Residential One tile: Car | Car\n",
+        )
+        .unwrap();
+        let intersection = map.intersections[1].clone();
+        let closest = |intersection: &Arc<Mutex<Intersection>>| {
+            let intersection = intersection.lock().unwrap();
+            intersection
+                .connections
+                .iter()
+                .map(|c| c.lock().unwrap().center.distance(&intersection.center))
+                .fold(f64::MAX, f64::min)
+        };
+
+        // The roads move out of the way of the ring as it grows
+        for radius in [8.0, 24.0] {
+            map.set_intersection_kind(&intersection, IntersectionKind::Roundabout { radius });
+            assert!(closest(&intersection) >= radius + 8.0);
+            assert_eq!(intersection.lock().unwrap().connections.len(), 4);
+            assert_eq!(intersection.lock().unwrap().roads.len(), 2);
+        }
+        let ring = closest(&intersection);
+        map.set_intersection_kind(&intersection, IntersectionKind::Point);
+        assert!(closest(&intersection) < ring);
+    }
+
+    #[test]
+    fn test_set_od() {
+        let mut map =
+            network::parse("intersection 16 16\nintersection 112 16\nroad 0 1 Residential One way: Car |\n").unwrap();
+        let od = OdMatrix::parse("I0,I1,60\nI1,I0,60\nI1,I0,30\nI0,I7,60\nI0,Commercial,60\n").unwrap();
+        let warnings = map.set_od(od);
+        assert_eq!(
+            warnings,
+            [
+                "No way out of I1, trips from it to I0 won't start",
+                "No way into I7, trips to it from I0 won't start",
+                "No way into Commercial, trips to it from I0 won't start",
+            ]
+        );
+        assert_eq!(map.od.as_ref().unwrap().pairs.len(), 5);
     }
 }
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{agent::Agent, demand::TICKS_PER_HOUR, lane::Lane, map::Map};
+
+// Five minutes per bin unless asked otherwise
+pub const BIN_TICKS: u64 = 300;
+// Agents slower than this count as queued
+const QUEUE_SPEED: f64 = 0.05;
+
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct LaneCounts {
+    // Agents that came onto the lane
+    pub entries: u32,
+    // Agents on the lane summed over the ticks
+    pub agent_ticks: u32,
+    pub speed_sum: f64,
+    // Most agents queued at once
+    pub max_queue: u32,
+}
+
+pub struct Bin {
+    pub start: u64,
+    // Just past the last tick counted
+    pub end: u64,
+    pub ticks: u64,
+    // By lane id
+    pub lanes: HashMap<usize, LaneCounts>,
+}
+
+// Per lane flow, density, speed and queues over the run, in time bins.
+pub struct Metrics {
+    pub bin_ticks: u64,
+    pub bins: Vec<Bin>,
+    // Lane each agent was on last tick
+    last_lanes: HashMap<usize, usize>,
+    // Agents that came onto each lane over the whole run, by lane id
+    volumes: HashMap<usize, u32>,
+}
+
+impl Metrics {
+    pub fn new(bin_ticks: u64) -> Self {
+        Self { bin_ticks: bin_ticks.max(1), bins: Vec::new(), last_lanes: HashMap::new(), volumes: HashMap::new() }
+    }
+
+    // Count where the agents are at the given tick.
+    pub fn record(&mut self, tick: u64, agents: &[Arc<Mutex<Agent>>]) {
+        let start = tick - tick % self.bin_ticks;
+        if !matches!(self.bins.last(), Some(bin) if bin.start == start) {
+            self.bins.push(Bin { start, end: start, ticks: 0, lanes: HashMap::new() });
+        }
+        let bin = self.bins.last_mut().unwrap();
+        bin.end = tick + 1;
+        bin.ticks += 1;
+
+        let mut last_lanes = HashMap::new();
+        let mut queues: HashMap<usize, u32> = HashMap::new();
+        for agent in agents {
+            let agent = agent.lock().unwrap();
+            let lane = agent.l.lock().unwrap().id;
+            let counts = bin.lanes.entry(lane).or_default();
+            if self.last_lanes.get(&agent.id) != Some(&lane) {
+                counts.entries += 1;
+                *self.volumes.entry(lane).or_insert(0) += 1;
+            }
+            counts.agent_ticks += 1;
+            counts.speed_sum += agent.speed;
+            if agent.speed < QUEUE_SPEED {
+                *queues.entry(lane).or_insert(0) += 1;
+            }
+            last_lanes.insert(agent.id, lane);
+        }
+        for (lane, queue) in queues {
+            let counts = bin.lanes.get_mut(&lane).unwrap();
+            counts.max_queue = counts.max_queue.max(queue);
+        }
+        self.last_lanes = last_lanes;
+    }
+
+    // Agents that came onto the lane over the whole run.
+    pub fn volume(&self, lane: &Arc<Mutex<Lane>>) -> u32 {
+        self.volumes.get(&lane.lock().unwrap().id).copied().unwrap_or(0)
+    }
+
+    // A row per bin and lane of the map, lanes numbered like Map::lanes with
+    // the crosswalks after them.
+    // Flow is in agents per hour, density in agents per km and speed in
+    // meters per tick.
+    pub fn to_csv(&self, map: &Map) -> String {
+        let mut lanes = Vec::new();
+        for (r, road) in map.roads.iter().enumerate() {
+            for lane in &road.lock().unwrap().lanes {
+                lanes.push((format!("road {}", r), lane.clone()));
+            }
+        }
+        for (i, intersection) in map.intersections.iter().enumerate() {
+            for lane in &intersection.lock().unwrap().lanes {
+                lanes.push((format!("intersection {}", i), lane.clone()));
+            }
+        }
+        for (i, intersection) in map.intersections.iter().enumerate() {
+            for lane in &intersection.lock().unwrap().crosswalks {
+                lanes.push((format!("intersection {}", i), lane.clone()));
+            }
+        }
+
+        let mut csv = String::from("bin_start,bin_end,lane,location,lane_kind,length,flow,density,mean_speed,queue\n");
+        let empty = LaneCounts::default();
+        for bin in &self.bins {
+            let ticks = bin.ticks as f64;
+            for (l, (location, lane)) in lanes.iter().enumerate() {
+                let lane = lane.lock().unwrap();
+                let counts = bin.lanes.get(&lane.id).unwrap_or(&empty);
+                let length = lane.length();
+                let flow = counts.entries as f64 * TICKS_PER_HOUR as f64 / ticks;
+                let density = counts.agent_ticks as f64 / ticks / length.max(0.01) * 1000.0;
+                let mean_speed = match counts.agent_ticks {
+                    0 => String::new(),
+                    n => format!("{:.3}", counts.speed_sum / n as f64),
+                };
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{:.2},{:.1},{:.2},{},{}\n",
+                    bin.start,
+                    bin.end,
+                    l,
+                    location,
+                    lane.kind.name(),
+                    length,
+                    flow,
+                    density,
+                    mean_speed,
+                    counts.max_queue
+                ));
+            }
+        }
+        csv
+    }
+
+    pub fn save(&self, map: &Map, path: &str) -> io::Result<()> {
+        if path.ends_with(".parquet") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Parquet isn't supported, use CSV"));
+        }
+        fs::write(path, self.to_csv(map))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::AgentKind, network};
+
+    #[test]
+    fn test_metrics() {
+        let mut map = network::parse(
+            "intersection 16 16\nintersection 112 16\nroad 0 1 Residential Two tile: Car Pedestrian | Car Pedestrian\n",
+        )
+        .unwrap();
+        map.metrics = Metrics::new(10);
+        let lane = map.roads[0].lock().unwrap().lanes[0].clone();
+        map.spawn_agent(AgentKind::Car, lane.clone(), 0.0);
+        for _ in 0..25 {
+            map.update();
+        }
+
+        let bins = &map.metrics.bins;
+        let spans: Vec<(u64, u64, u64)> = bins.iter().map(|bin| (bin.start, bin.end, bin.ticks)).collect();
+        assert_eq!(spans, [(0, 10, 9), (10, 20, 10), (20, 26, 6)]);
+        let id = lane.lock().unwrap().id;
+        let counts = &bins[1].lanes[&id];
+        assert_eq!((counts.entries, counts.agent_ticks), (0, 10));
+        assert_eq!(bins[0].lanes[&id].entries, 1);
+        assert_eq!(map.metrics.volume(&lane), 1);
+
+        let csv = map.metrics.to_csv(&map);
+        let crosswalks: usize = map.intersections.iter().map(|i| i.lock().unwrap().crosswalks.len()).sum();
+        let lanes = map.lanes().len() + crosswalks;
+        assert!(crosswalks > 0);
+        assert_eq!(csv.lines().count(), 1 + 3 * lanes);
+        let row: Vec<&str> = csv.lines().nth(lanes + 1).unwrap().split(',').collect();
+        assert_eq!(&row[..5], ["10", "20", "0", "road 0", "Car"]);
+        // One agent along a lane of the road, accelerating, never queued
+        assert_eq!(row[6], "0.0");
+        assert_eq!(row[7], format!("{:.2}", 1000.0 / lane.lock().unwrap().length()));
+        assert_eq!(row[9], "0");
+        assert!(map.metrics.save(&map, "metrics.parquet").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    connection::{Connection, ConnectionKind, SignKind},
+    intersection::{Intersection, IntersectionKind},
+    lane::LaneKind,
+    map::Map,
+    osm::GeoReference,
+    property::PropertyKind,
+    road::RoadKind,
+    road_profile::RoadProfile,
+};
+
+// A connection and the intersection it is on
+type Located = (Arc<Mutex<Connection>>, Arc<Mutex<Intersection>>);
+
+// A network is saved one item per line:
+//   geo <latitude> <longitude> <cosine of the mean latitude>
+//   intersection <x> <y> [roundabout <radius>]
+//   road <intersection> <intersection> <road kind> <profile>
+//   zone <road> <property> <property kind>
+//   sign <connection> <sign kind>
+//   sink <connection>
+//   movement <connection> <connection> <lane kind>
+// Items refer to earlier ones by their position in the file. Connections are
+// given by their place on a road, as <road> <end> <In|Out> <lane>, which stays
+// the same however the intersections order them.
+pub fn to_text(map: &Map) -> String {
+    let mut text = String::from("# roads network\n");
+    if let Some(geo) = &map.geo {
+        text.push_str(&format!("geo {} {} {}\n", geo.lat, geo.lon, geo.cos_lat));
+    }
+    let index_of = |intersection: &Arc<Mutex<Intersection>>| {
+        map.intersections.iter().position(|i| Arc::ptr_eq(i, intersection))
+    };
+
+    for intersection in &map.intersections {
+        let intersection = intersection.lock().unwrap();
+        let (x, y) = (intersection.center.x, intersection.center.y);
+        match intersection.kind {
+            IntersectionKind::Point => text.push_str(&format!("intersection {} {}\n", x, y)),
+            IntersectionKind::Roundabout { radius } => {
+                text.push_str(&format!("intersection {} {} roundabout {}\n", x, y, radius))
+            }
+        }
+    }
+
+    for (r, road) in map.roads.iter().enumerate() {
+        let road = road.lock().unwrap();
+        let (i0, i2) = match (index_of(&road.i0), index_of(&road.i2)) {
+            (Some(i0), Some(i2)) => (i0, i2),
+            _ => continue,
+        };
+        text.push_str(&format!("road {} {} {} {}\n", i0, i2, road.kind.name(), road.profile.to_line()));
+        for (p, property) in road.properties.iter().enumerate() {
+            if property.kind != PropertyKind::Vacant {
+                text.push_str(&format!("zone {} {} {}\n", r, p, property.kind.name()));
+            }
+        }
+    }
+
+    let mut places = Vec::new();
+    for (r, road) in map.roads.iter().enumerate() {
+        let road = road.lock().unwrap();
+        for connection in &road.connections {
+            if let Some((end, kind, lane)) = road.connection_place(connection) {
+                places.push((connection.clone(), format!("{} {} {} {}", r, end, kind.name(), lane)));
+            }
+        }
+    }
+    let place_of = |connection: &Arc<Mutex<Connection>>| places.iter().find(|(c, _)| Arc::ptr_eq(c, connection)).map(|(_, p)| p);
+
+    for (connection, place) in &places {
+        let connection = connection.lock().unwrap();
+        if connection.sign != SignKind::None {
+            text.push_str(&format!("sign {} {}\n", place, connection.sign.name()));
+        }
+        if connection.sink {
+            text.push_str(&format!("sink {}\n", place));
+        }
+    }
+
+    for intersection in &map.intersections {
+        let intersection = intersection.lock().unwrap();
+        for (c0, c1, lane_kind) in &intersection.movements {
+            if let (Some(c0), Some(c1)) = (place_of(c0), place_of(c1)) {
+                text.push_str(&format!("movement {} {} {}\n", c0, c1, lane_kind.name()));
+            }
+        }
+    }
+    text
+}
+
+pub fn parse(text: &str) -> Result<Map, String> {
+    let mut map = Map::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |what: &str| format!("Line {}: {} in \"{}\"", number + 1, what, line);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let number_at = |i: usize| -> Result<f64, String> {
+            fields
+                .get(i)
+                .and_then(|f| f.parse::<f64>().ok())
+                .ok_or(error("bad number"))
+        };
+        let index_at = |i: usize| -> Result<usize, String> {
+            fields
+                .get(i)
+                .and_then(|f| f.parse::<usize>().ok())
+                .ok_or(error("bad index"))
+        };
+        let intersection_at = |map: &Map, i: usize| {
+            map.intersections.get(index_at(i)?).cloned().ok_or(error("unknown intersection"))
+        };
+        // Connection at its place on a road
+        let connection_at = |map: &Map, i: usize| -> Result<Located, String> {
+            let road = map.roads.get(index_at(i)?).ok_or(error("unknown road"))?;
+            let road = road.lock().unwrap();
+            let end = index_at(i + 1)?;
+            let kind = fields
+                .get(i + 2)
+                .and_then(|k| ConnectionKind::from_name(k))
+                .ok_or(error("unknown connection kind"))?;
+            let connection = road.connection(end, kind, index_at(i + 3)?).ok_or(error("unknown connection"))?;
+            let intersection = if end == 0 { road.i0.clone() } else { road.i2.clone() };
+            Ok((connection, intersection))
+        };
+
+        match fields[0] {
+            "geo" => {
+                map.geo = Some(GeoReference { lat: number_at(1)?, lon: number_at(2)?, cos_lat: number_at(3)? });
+            }
+            "intersection" => {
+                let (x, y) = (number_at(1)?, number_at(2)?);
+                let intersection = match fields.get(3) {
+                    Some(&"roundabout") => Intersection::new_roundabout(x, y, number_at(4)?),
+                    Some(_) => return Err(error("unknown intersection kind")),
+                    None => Intersection::new(x, y),
+                };
+                map.intersections.push(Arc::new(Mutex::new(intersection)));
+            }
+            "road" => {
+                let i0 = intersection_at(&map, 1)?;
+                let i2 = intersection_at(&map, 2)?;
+                let kind = fields
+                    .get(3)
+                    .and_then(|k| RoadKind::from_name(k))
+                    .ok_or(error("unknown road kind"))?;
+                // The profile is the rest of the line
+                let profile = line.splitn(5, char::is_whitespace).nth(4).ok_or(error("missing profile"))?;
+                let profile = RoadProfile::parse(profile).map_err(|e| error(&e))?;
+                map.add_road(i0, i2, Arc::new(Mutex::new(profile)), kind);
+            }
+            "zone" => {
+                let road = map.roads.get(index_at(1)?).cloned().ok_or(error("unknown road"))?;
+                let kind = fields
+                    .get(3)
+                    .and_then(|k| PropertyKind::from_name(k))
+                    .ok_or(error("unknown property kind"))?;
+                let mut road = road.lock().unwrap();
+                let property = road.properties.get_mut(index_at(2)?).ok_or(error("unknown property"))?;
+                property.kind = kind;
+            }
+            "sign" | "sink" => {
+                let (connection, _) = connection_at(&map, 1)?;
+                let mut connection = connection.lock().unwrap();
+                if fields[0] == "sink" {
+                    connection.sink = true;
+                } else {
+                    connection.sign = fields
+                        .get(5)
+                        .and_then(|k| SignKind::from_name(k))
+                        .ok_or(error("unknown sign kind"))?;
+                }
+            }
+            "movement" => {
+                let (c0, intersection) = connection_at(&map, 1)?;
+                let (c1, _) = connection_at(&map, 5)?;
+                let lane_kind = fields
+                    .get(9)
+                    .and_then(|k| LaneKind::from_name(k))
+                    .ok_or(error("unknown lane kind"))?;
+                intersection.lock().unwrap().add_movement(c0, c1, lane_kind);
+            }
+            _ => return Err(error("unknown item")),
+        }
+    }
+    Ok(map)
+}
+
+pub fn save(map: &Map, path: &str) -> io::Result<()> {
+    fs::write(path, to_text(map))
+}
+
+pub fn load(path: &str) -> io::Result<Map> {
+    parse(&fs::read_to_string(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "# roads network\n\
+            geo 51.001 4 0.629\n\
+            intersection 0 0\n\
+            intersection 96 0 roundabout 12\n\
+            intersection 96 96\n\
+            road 0 1 Residential Two tile: Car Bike Pedestrian | Car Bike Pedestrian\n\
+            road 1 2 Arterial One way: Car Car |\n\
+            zone 0 1 Residential\n\
+            sign 0 1 In 0 Stop\n\
+            sink 1 0 Out 1\n";
+        let map = parse(text).unwrap();
+        assert_eq!(map.intersections.len(), 3);
+        assert_eq!(map.roads.len(), 2);
+        assert_eq!(parse(&to_text(&map)).map(|m| to_text(&m)), Ok(to_text(&map)));
+        assert!(parse("road 0 1 Residential One tile: Car | Car").is_err());
+    }
+
+    #[test]
+    fn test_round_trip_flipped() {
+        let mut map = parse(
+            "intersection 0 0\n\
+             intersection 96 0\n\
+             intersection 96 96\n\
+             road 0 1 Residential Two tile: Car Bike | Car Pedestrian\n\
+             road 1 2 Residential One tile: Car | Car\n\
+             sign 1 0 In 0 Stop\n\
+             sign 0 1 In 0 Yield\n",
+        )
+        .unwrap();
+        // The flipped road's connections go to the back of its intersections
+        map.flip_road(&map.roads[0].clone());
+        let text = to_text(&map);
+        assert_eq!(to_text(&parse(&text).unwrap()), text);
+        assert!(text.contains("sign 1 0 In 0 Stop\n"));
+        assert!(text.contains("sign 0 1 In 0 Yield\n"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::{fs, io};
+
+use rand::Rng;
+
+use crate::demand::TICKS_PER_HOUR;
+
+// Measured demand between two places. Places are intersections, "I0" being
+// the first one, or zones: every property zoned "Residential", "Commercial"
+// or "Industrial".
+pub struct OdPair {
+    pub origin: String,
+    pub destination: String,
+    pub trips_per_hour: f64,
+    // Tick the next trip starts at
+    next_arrival: f64,
+}
+
+pub struct OdMatrix {
+    pub pairs: Vec<OdPair>,
+}
+
+impl OdMatrix {
+    // One pair per line as "origin,destination,trips per hour".
+    pub fn parse(text: &str) -> Result<OdMatrix, String> {
+        let mut pairs = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+            if fields.len() != 3 {
+                return Err(format!("Line {}: expected 3 fields, got {}", number + 1, fields.len()));
+            }
+            let trips_per_hour = match fields[2].parse::<f64>() {
+                Ok(trips) if trips >= 0.0 => trips,
+                // Header
+                Err(_) if number == 0 => continue,
+                _ => return Err(format!("Line {}: bad trips per hour \"{}\"", number + 1, fields[2])),
+            };
+
+            let mut pair = OdPair {
+                origin: fields[0].to_string(),
+                destination: fields[1].to_string(),
+                trips_per_hour,
+                next_arrival: 0.0,
+            };
+            pair.next_arrival = pair.interval();
+            pairs.push(pair);
+        }
+        Ok(OdMatrix { pairs })
+    }
+
+    pub fn load(path: &str) -> io::Result<OdMatrix> {
+        OdMatrix::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Trips starting this tick as (origin, destination). Arrivals are Poisson,
+    // so the time between them is exponential.
+    pub fn arrivals(&mut self, tick: u64) -> Vec<(String, String)> {
+        let mut trips = Vec::new();
+        for pair in &mut self.pairs {
+            while pair.next_arrival <= tick as f64 {
+                trips.push((pair.origin.clone(), pair.destination.clone()));
+                pair.next_arrival += pair.interval();
+            }
+        }
+        trips
+    }
+}
+
+impl OdPair {
+    fn interval(&self) -> f64 {
+        if self.trips_per_hour <= 0.0 {
+            return f64::MAX;
+        }
+        let u: f64 = rand::thread_rng().gen_range(f64::EPSILON..1.0);
+        -u.ln() * TICKS_PER_HOUR as f64 / self.trips_per_hour
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let od = OdMatrix::parse("origin,destination,trips\nI0, I3, 120\n# note\nResidential,Commercial,30\n").unwrap();
+        assert_eq!(od.pairs.len(), 2);
+        assert_eq!(od.pairs[0].destination, "I3");
+        assert_eq!(od.pairs[1].trips_per_hour, 30.0);
+        assert!(OdMatrix::parse("I0,I1").is_err());
+        assert!(OdMatrix::parse("I0,I1,-1").is_err());
+    }
+
+    #[test]
+    fn test_poisson_arrivals() {
+        let mut od = OdMatrix::parse("I0,I1,60").unwrap();
+        let hours = 100;
+        let mut count = 0;
+        for tick in 0..TICKS_PER_HOUR * hours {
+            count += od.arrivals(tick).len();
+        }
+        // 6000 expected, give or take a few standard deviations
+        assert!(count > 5600 && count < 6400, "{} arrivals", count);
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    curve::Curve,
+    intersection::Intersection,
+    lane::{Lane, LaneKind},
+    map::{key, Map},
+};
+
+fn lane_type(kind: LaneKind) -> &'static str {
+    match kind {
+        LaneKind::Car => "driving",
+        LaneKind::Bike => "biking",
+        LaneKind::Pedestrian => "sidewalk",
+        LaneKind::Median => "median",
+        LaneKind::Shoulder => "shoulder",
+        LaneKind::Parking => "parking",
+        LaneKind::Bus => "bus",
+        LaneKind::Tram => "tram",
+    }
+}
+
+// A reference line record. OpenDRIVE has y pointing up and counts angles
+// counterclockwise, so everything is mirrored from the screen.
+struct Geometry {
+    x: f64,
+    y: f64,
+    hdg: f64,
+    length: f64,
+    // Zero for lines, positive when turning left
+    curvature: f64,
+}
+
+impl Geometry {
+    fn from_curve(curve: &Curve) -> Geometry {
+        let (heading, curvature) = if curve.is_curved {
+            let a0 = curve.c.angle(&curve.n0);
+            let radius = curve.c.distance(&curve.n0);
+            // Growing angles turn clockwise on the screen
+            if curve.is_reversed {
+                (a0 - PI / 2.0, 1.0 / radius)
+            } else {
+                (a0 + PI / 2.0, -1.0 / radius)
+            }
+        } else {
+            (curve.n0.angle(&curve.n1), 0.0)
+        };
+        Geometry {
+            x: curve.n0.x,
+            y: -curve.n0.y,
+            hdg: (-heading).rem_euclid(2.0 * PI),
+            length: curve.length(),
+            curvature,
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        let shape = if self.curvature == 0.0 {
+            String::from("<line/>")
+        } else {
+            format!("<arc curvature=\"{:.8}\"/>", self.curvature)
+        };
+        format!(
+            "        <planView>\n            <geometry s=\"0\" x=\"{:.4}\" y=\"{:.4}\" hdg=\"{:.8}\" length=\"{:.4}\">{}</geometry>\n        </planView>\n",
+            self.x, self.y, self.hdg, self.length, shape
+        )
+    }
+}
+
+fn lane_xml(id: i32, kind: LaneKind, width: f64) -> String {
+    format!(
+        "                    <lane id=\"{}\" type=\"{}\" level=\"false\"><width sOffset=\"0\" a=\"{:.2}\" b=\"0\" c=\"0\" d=\"0\"/></lane>\n",
+        id,
+        lane_type(kind),
+        width
+    )
+}
+
+// Which side of its road a lane is on, as (road id, lane id): lanes going
+// from i0 to i2 are on the right with negative ids, counted from the center.
+fn road_lanes(map: &Map) -> HashMap<usize, (usize, i32)> {
+    let mut ids = HashMap::new();
+    for (r, road) in map.roads.iter().enumerate() {
+        let road = road.lock().unwrap();
+        let [right, left] = road.lanes_by_direction();
+        for (i, lane) in right.iter().enumerate() {
+            ids.insert(key(lane), (r, -(i as i32) - 1));
+        }
+        for (i, lane) in left.iter().enumerate() {
+            ids.insert(key(lane), (r, i as i32 + 1));
+        }
+    }
+    ids
+}
+
+// Roads with their lane sections, then one junction per intersection with a
+// connecting road for each of its lanes.
+pub fn to_xodr(map: &Map) -> String {
+    let ids = road_lanes(map);
+    let index_of = |intersection: &Arc<Mutex<Intersection>>| {
+        map.intersections.iter().position(|i| Arc::ptr_eq(i, intersection))
+    };
+    // Connecting roads are numbered after the roads
+    let mut connecting: HashMap<usize, usize> = HashMap::new();
+    for intersection in &map.intersections {
+        for lane in &intersection.lock().unwrap().lanes {
+            let id = map.roads.len() + connecting.len();
+            connecting.insert(key(lane), id);
+        }
+    }
+
+    let mut bounds = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    let mut roads = String::new();
+    for (r, road) in map.roads.iter().enumerate() {
+        let road = road.lock().unwrap();
+        let geometry = Geometry::from_curve(&road.curve);
+        for n in [road.curve.n0, road.curve.n1].iter() {
+            bounds = (bounds.0.min(n.x), bounds.1.min(-n.y), bounds.2.max(n.x), bounds.3.max(-n.y));
+        }
+
+        let mut links = String::new();
+        for (element, intersection) in [("predecessor", &road.i0), ("successor", &road.i2)] {
+            if let Some(j) = index_of(intersection) {
+                links.push_str(&format!("<{} elementType=\"junction\" elementId=\"{}\"/>", element, j));
+            }
+        }
+
+        let mut right = String::new();
+        let mut left = String::new();
+        for lane in &road.lanes {
+            let lane_lock = lane.lock().unwrap();
+            match ids.get(&key(lane)) {
+                Some((_, id)) if *id < 0 => right.push_str(&lane_xml(*id, lane_lock.kind, lane_lock.width)),
+                Some((_, id)) => left.insert_str(0, &lane_xml(*id, lane_lock.kind, lane_lock.width)),
+                None => {}
+            }
+        }
+
+        roads.push_str(&format!(
+            "    <road name=\"{}\" length=\"{:.4}\" id=\"{}\" junction=\"-1\">\n        <link>{}</link>\n        <type s=\"0\" type=\"town\"/>\n{}",
+            road.kind.name(),
+            geometry.length,
+            r,
+            links,
+            geometry.to_xml()
+        ));
+        roads.push_str("        <lanes>\n            <laneSection s=\"0\">\n");
+        if !left.is_empty() {
+            roads.push_str(&format!("                <left>\n{}                </left>\n", left));
+        }
+        roads.push_str("                <center><lane id=\"0\" type=\"none\" level=\"false\"/></center>\n");
+        if !right.is_empty() {
+            roads.push_str(&format!("                <right>\n{}                </right>\n", right));
+        }
+        roads.push_str("            </laneSection>\n        </lanes>\n    </road>\n");
+    }
+
+    let mut junctions = String::new();
+    for (j, intersection) in map.intersections.iter().enumerate() {
+        let intersection = intersection.lock().unwrap();
+        if intersection.lanes.is_empty() {
+            continue;
+        }
+        let mut connections = String::new();
+        for lane in &intersection.lanes {
+            let id = connecting[&key(lane)];
+            let lane_lock = lane.lock().unwrap();
+            let geometry = Geometry::from_curve(&lane_lock.curve);
+            let (c0, c1) = (lane_lock.c0.clone(), lane_lock.c1.clone());
+            let (kind, width) = (lane_lock.kind, lane_lock.width);
+            drop(lane_lock);
+
+            // What the lane comes from and leads to: a road lane, or another
+            // lane of the intersection on roundabouts
+            let incoming = c0.lock().unwrap().in_lane.first().cloned();
+            let outgoing = c1.lock().unwrap().out_lane.first().cloned();
+            let link = |element: &str, other: &Option<Arc<Mutex<Lane>>>, road_end: &str| -> String {
+                let other = match other {
+                    Some(other) => other,
+                    None => return String::new(),
+                };
+                if let Some((r, id)) = ids.get(&key(other)) {
+                    // Right lanes leave the road at its end
+                    let contact = if (*id < 0) == (road_end == "end") { "end" } else { "start" };
+                    format!("<{} elementType=\"road\" elementId=\"{}\" contactPoint=\"{}\"/>", element, r, contact)
+                } else if let Some(other) = connecting.get(&key(other)) {
+                    let contact = if element == "predecessor" { "end" } else { "start" };
+                    format!("<{} elementType=\"road\" elementId=\"{}\" contactPoint=\"{}\"/>", element, other, contact)
+                } else {
+                    String::new()
+                }
+            };
+            let links = format!("{}{}", link("predecessor", &incoming, "end"), link("successor", &outgoing, "start"));
+
+            roads.push_str(&format!(
+                "    <road name=\"J{}\" length=\"{:.4}\" id=\"{}\" junction=\"{}\">\n        <link>{}</link>\n{}",
+                j,
+                geometry.length,
+                id,
+                j,
+                links,
+                geometry.to_xml()
+            ));
+            // The reference line runs down the middle of the lane
+            roads.push_str(&format!(
+                "        <lanes>\n            <laneOffset s=\"0\" a=\"{:.2}\" b=\"0\" c=\"0\" d=\"0\"/>\n            <laneSection s=\"0\">\n                <center><lane id=\"0\" type=\"none\" level=\"false\"/></center>\n                <right>\n{}                </right>\n            </laneSection>\n        </lanes>\n    </road>\n",
+                width / 2.0,
+                lane_xml(-1, kind, width)
+            ));
+
+            // Junction connections start on the roads coming in
+            if let Some((r, from)) = incoming.as_ref().and_then(|l| ids.get(&key(l))) {
+                connections.push_str(&format!(
+                    "        <connection id=\"{}\" incomingRoad=\"{}\" connectingRoad=\"{}\" contactPoint=\"{}\"><laneLink from=\"{}\" to=\"-1\"/></connection>\n",
+                    connections.matches("<connection ").count(),
+                    r,
+                    id,
+                    "start",
+                    from
+                ));
+            }
+        }
+        junctions.push_str(&format!("    <junction name=\"J{}\" id=\"{}\">\n{}    </junction>\n", j, j, connections));
+    }
+
+    if bounds.0 > bounds.2 {
+        bounds = (0.0, 0.0, 0.0, 0.0);
+    }
+    let geo_reference = match &map.geo {
+        Some(geo) => format!("<geoReference><![CDATA[{}]]></geoReference>", geo.proj_string()),
+        None => String::new(),
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<OpenDRIVE>\n    <header revMajor=\"1\" revMinor=\"6\" name=\"roads\" version=\"1\" north=\"{:.4}\" south=\"{:.4}\" east=\"{:.4}\" west=\"{:.4}\">{}</header>\n{}{}</OpenDRIVE>\n",
+        bounds.3, bounds.1, bounds.2, bounds.0, geo_reference, roads, junctions
+    )
+}
+
+pub fn save(map: &Map, path: &str) -> io::Result<()> {
+    fs::write(path, to_xodr(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{network, node::Node};
+
+    // Where the OpenDRIVE record ends up after its length.
+    fn end(g: &Geometry) -> (f64, f64) {
+        if g.curvature == 0.0 {
+            (g.x + g.length * g.hdg.cos(), g.y + g.length * g.hdg.sin())
+        } else {
+            let k = g.curvature;
+            let a = g.hdg + k * g.length;
+            (g.x + (a.sin() - g.hdg.sin()) / k, g.y - (a.cos() - g.hdg.cos()) / k)
+        }
+    }
+
+    #[test]
+    fn test_geometry() {
+        let curves = [
+            Curve::new(Node::new(0.0, 0.0), Node::new(40.0, 30.0), 0.3, 0.3 + PI),
+            Curve::new(Node::new(0.0, 0.0), Node::new(20.0, 20.0), 0.0, PI / 2.0 + PI),
+            Curve::new(Node::new(0.0, 0.0), Node::new(20.0, -20.0), 0.0, -PI / 2.0 + PI),
+        ];
+        for curve in curves.iter() {
+            let (x, y) = end(&Geometry::from_curve(curve));
+            assert!((x - curve.n1.x).abs() < 0.01 && (y + curve.n1.y).abs() < 0.01, "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_xodr() {
+        let map = network::parse(
+            "intersection 0 0\n\
+             intersection 96 0\n\
+             intersection 96 96\n\
+             road 0 1 Residential One tile: Car | Car\n\
+             road 1 2 Residential Two tile: Car Bike Pedestrian | Car Bike Pedestrian\n",
+        )
+        .unwrap();
+        let xodr = to_xodr(&map);
+        let lanes: usize = map.intersections.iter().map(|i| i.lock().unwrap().lanes.len()).sum();
+        assert_eq!(xodr.matches("<road ").count(), 2 + lanes);
+        assert_eq!(xodr.matches("<junction ").count(), 3);
+        assert!(xodr.matches("<connection ").count() > 0);
+        assert!(xodr.contains("<lane id=\"-3\" type=\"sidewalk\""));
+        assert!(xodr.contains("<predecessor elementType=\"road\" elementId=\"0\" contactPoint=\"end\"/>"));
+        assert_eq!(xodr.matches("<OpenDRIVE>").count(), xodr.matches("</OpenDRIVE>").count());
+    }
+}
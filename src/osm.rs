@@ -0,0 +1,468 @@
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    intersection::Intersection,
+    lane::LaneKind,
+    map::Map,
+    node::Node,
+    pbf,
+    road::RoadKind,
+    road_profile::RoadProfile,
+    TILE,
+};
+
+// Mean radius of the earth in meters, one meter is one unit on the map
+const EARTH_RADIUS: f64 = 6_371_000.0;
+// Room left around the imported network
+const MARGIN: f64 = TILE * 2.0;
+// Bends sharper than this get an intersection of their own to keep the shape
+const MAX_BEND: f64 = PI / 6.0;
+// Shorter stretches are merged into the next one
+const MIN_SEGMENT: f64 = TILE;
+
+pub struct OsmNode {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+pub struct OsmWay {
+    pub nodes: Vec<u64>,
+    pub tags: HashMap<String, String>,
+}
+
+pub struct OsmData {
+    pub nodes: HashMap<u64, OsmNode>,
+    pub ways: Vec<OsmWay>,
+}
+
+// Tag in the XML, as (name, attributes, is a closing tag, closes itself)
+type Element = (String, HashMap<String, String>, bool, bool);
+
+// Character for an entity between '&' and ';', named or numeric.
+fn entity(name: &str) -> Option<char> {
+    match name {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "amp" => Some('&'),
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix('x').or_else(|| number.strip_prefix('X')) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+// Unknown entities are left as they are.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let name = rest.find(';').map(|end| &rest[1..end]);
+        match name.and_then(|name| Some((name, entity(name)?))) {
+            Some((name, c)) => {
+                unescaped.push(c);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// End of the tag text starts in, '>' in quoted values doesn't count.
+fn tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+// Just enough XML for OSM files: tags and their attributes, text is ignored.
+fn elements(text: &str) -> Result<Vec<Element>, String> {
+    let mut elements = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if rest.starts_with("!--") {
+            let end = rest.find("-->").ok_or("Unclosed comment")?;
+            rest = &rest[end + 3..];
+            continue;
+        }
+        let end = tag_end(rest).ok_or("Unclosed tag")?;
+        let mut tag = &rest[..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        let is_closing = tag.starts_with('/');
+        let closes_itself = tag.ends_with('/');
+        tag = tag.trim_start_matches('/').trim_end_matches('/');
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let name = tag[..name_end].to_string();
+
+        let mut attributes = HashMap::new();
+        let mut attribute = tag[name_end..].trim_start();
+        while let Some(equals) = attribute.find('=') {
+            let key = attribute[..equals].trim().to_string();
+            let value = attribute[equals + 1..].trim_start();
+            let quote = value.chars().next().ok_or(format!("Missing value for {} in <{}>", key, name))?;
+            if quote != '"' && quote != '\'' {
+                return Err(format!("Unquoted value for {} in <{}>", key, name));
+            }
+            let value = &value[1..];
+            let value_end = value.find(quote).ok_or(format!("Unclosed value for {} in <{}>", key, name))?;
+            attributes.insert(key, unescape(&value[..value_end]));
+            attribute = value[value_end + 1..].trim_start();
+        }
+        elements.push((name, attributes, is_closing, closes_itself));
+    }
+    Ok(elements)
+}
+
+pub fn parse(text: &str) -> Result<OsmData, String> {
+    let mut data = OsmData { nodes: HashMap::new(), ways: Vec::new() };
+    let mut way: Option<OsmWay> = None;
+
+    let number = |attributes: &HashMap<String, String>, key: &str| -> Result<f64, String> {
+        attributes
+            .get(key)
+            .and_then(|v| v.parse::<f64>().ok())
+            .ok_or(format!("Bad or missing {}", key))
+    };
+    let id = |attributes: &HashMap<String, String>, key: &str| -> Result<u64, String> {
+        attributes
+            .get(key)
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or(format!("Bad or missing {}", key))
+    };
+
+    for (name, attributes, is_closing, closes_itself) in elements(text)? {
+        match (name.as_str(), is_closing) {
+            ("node", false) => {
+                let node = OsmNode { lat: number(&attributes, "lat")?, lon: number(&attributes, "lon")? };
+                data.nodes.insert(id(&attributes, "id")?, node);
+            }
+            ("way", false) => {
+                let new_way = OsmWay { nodes: Vec::new(), tags: HashMap::new() };
+                if closes_itself {
+                    data.ways.push(new_way);
+                } else {
+                    way = Some(new_way);
+                }
+            }
+            ("way", true) => {
+                if let Some(way) = way.take() {
+                    data.ways.push(way);
+                }
+            }
+            ("nd", false) => {
+                if let Some(way) = &mut way {
+                    way.nodes.push(id(&attributes, "ref")?);
+                }
+            }
+            ("tag", false) => {
+                // Tags of nodes and relations don't matter
+                if let (Some(way), Some(k), Some(v)) = (&mut way, attributes.get("k"), attributes.get("v")) {
+                    way.tags.insert(k.clone(), v.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(data)
+}
+
+// Kind of road for a highway tag, None for ways we don't import.
+fn road_kind(highway: &str) -> Option<RoadKind> {
+    match highway.trim_end_matches("_link") {
+        "motorway" | "trunk" => Some(RoadKind::Highway),
+        "primary" | "secondary" => Some(RoadKind::Arterial),
+        "tertiary" => Some(RoadKind::Collector),
+        "residential" | "unclassified" | "living_street" | "service" | "road" | "cycleway" | "footway"
+        | "pedestrian" | "path" | "steps" => Some(RoadKind::Residential),
+        _ => None,
+    }
+}
+
+// Profile of a way from its highway, lanes, oneway and cycleway tags,
+// right lanes run along the way.
+pub fn profile(tags: &HashMap<String, String>) -> Option<(RoadProfile, RoadKind)> {
+    let highway = tags.get("highway")?;
+    let kind = road_kind(highway)?;
+    let tag = |key: &str| tags.get(key).map(|v| v.as_str());
+    let count = |key: &str| tag(key).and_then(|v| v.parse::<usize>().ok());
+
+    let (right, left) = match highway.as_str() {
+        "cycleway" => (vec![LaneKind::Bike], vec![LaneKind::Bike]),
+        "footway" | "pedestrian" | "path" | "steps" => (vec![LaneKind::Pedestrian], vec![LaneKind::Pedestrian]),
+        _ => {
+            let oneway = matches!(tag("oneway"), Some("yes") | Some("true") | Some("1") | Some("-1"))
+                || tag("junction") == Some("roundabout")
+                || highway == "motorway";
+            let lanes = count("lanes");
+            let (forward, backward) = if oneway {
+                (lanes.unwrap_or(if kind == RoadKind::Highway { 2 } else { 1 }), 0)
+            } else {
+                let lanes = lanes.unwrap_or(2).max(2);
+                (
+                    count("lanes:forward").unwrap_or(lanes - lanes / 2),
+                    count("lanes:backward").unwrap_or(lanes / 2),
+                )
+            };
+            let mut right = vec![LaneKind::Car; forward.max(1)];
+            let mut left = vec![LaneKind::Car; backward];
+
+            // Bike lanes on the outside of the road
+            let is_bike_lane = |v: Option<&str>| matches!(v, Some("lane") | Some("track"));
+            let both = is_bike_lane(tag("cycleway")) || is_bike_lane(tag("cycleway:both"));
+            if both || is_bike_lane(tag("cycleway:right")) {
+                right.push(LaneKind::Bike);
+            }
+            if (both && !oneway) || is_bike_lane(tag("cycleway:left")) {
+                left.push(LaneKind::Bike);
+            }
+            (right, left)
+        }
+    };
+
+    let profile = RoadProfile {
+        name: format!("OSM {}", highway),
+        right_lane_kinds: right,
+        left_lane_kinds: left,
+    };
+    Some((profile, kind))
+}
+
+// Where an imported map lies on the earth: the top left corner of the
+// network and the cosine of its mean latitude, that shrinks the longitudes.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GeoReference {
+    pub lat: f64,
+    pub lon: f64,
+    pub cos_lat: f64,
+}
+
+// Maps drawn by hand start at 0° N 0° E.
+impl Default for GeoReference {
+    fn default() -> Self {
+        Self { lat: 0.0, lon: 0.0, cos_lat: 1.0 }
+    }
+}
+
+impl GeoReference {
+    // Map coordinates, in meters east and south of the origin.
+    pub fn project(&self, lat: f64, lon: f64) -> Node {
+        Node::new(
+            MARGIN + EARTH_RADIUS * (lon - self.lon).to_radians() * self.cos_lat,
+            MARGIN + EARTH_RADIUS * (self.lat - lat).to_radians(),
+        )
+    }
+
+    // The same projection for PROJ, with y pointing north.
+    pub fn proj_string(&self) -> String {
+        format!(
+            "+proj=eqc +lat_ts={} +lat_0={} +lon_0={} +x_0={} +y_0={} +R={} +units=m +no_defs",
+            self.cos_lat.acos().to_degrees(),
+            self.lat,
+            self.lon,
+            MARGIN,
+            -MARGIN,
+            EARTH_RADIUS
+        )
+    }
+
+    // Back to (lat, lon).
+    pub fn unproject(&self, n: &Node) -> (f64, f64) {
+        (
+            self.lat - ((n.y - MARGIN) / EARTH_RADIUS).to_degrees(),
+            self.lon + ((n.x - MARGIN) / EARTH_RADIUS / self.cos_lat).to_degrees(),
+        )
+    }
+}
+
+// Intersections at the junctions and ends of the imported ways, roads between them.
+pub fn to_map(data: &OsmData) -> Map {
+    let mut map = Map::new();
+
+    let mut ways: Vec<(Vec<u64>, RoadProfile, RoadKind)> = Vec::new();
+    for way in &data.ways {
+        if let Some((profile, kind)) = profile(&way.tags) {
+            let mut nodes: Vec<u64> = way.nodes.iter().copied().filter(|n| data.nodes.contains_key(n)).collect();
+            if way.tags.get("oneway").map(|v| v.as_str()) == Some("-1") {
+                nodes.reverse();
+            }
+            if nodes.len() >= 2 {
+                ways.push((nodes, profile, kind));
+            }
+        }
+    }
+
+    let used: Vec<&OsmNode> = ways.iter().flat_map(|w| w.0.iter().map(|n| &data.nodes[n])).collect();
+    if used.is_empty() {
+        return map;
+    }
+    // The north west corner ends up in the top left
+    let mean_lat = used.iter().map(|n| n.lat).sum::<f64>() / used.len() as f64;
+    let geo = GeoReference {
+        lat: used.iter().map(|n| n.lat).fold(f64::MIN, f64::max),
+        lon: used.iter().map(|n| n.lon).fold(f64::MAX, f64::min),
+        cos_lat: mean_lat.to_radians().cos(),
+    };
+    map.geo = Some(geo);
+    let position = |id: &u64| geo.project(data.nodes[id].lat, data.nodes[id].lon);
+
+    // Nodes shared by ways are junctions
+    let mut uses: HashMap<u64, usize> = HashMap::new();
+    for (nodes, _, _) in &ways {
+        for n in nodes {
+            *uses.entry(*n).or_default() += 1;
+        }
+    }
+
+    let mut intersections: HashMap<u64, Arc<Mutex<Intersection>>> = HashMap::new();
+    for (nodes, profile, kind) in &ways {
+        let profile = Arc::new(Mutex::new(profile.clone()));
+
+        // Where the way is split into roads
+        let mut stops = vec![nodes[0]];
+        for i in 1..nodes.len() {
+            let n = nodes[i];
+            let last = position(stops.last().unwrap());
+            let here = position(&n);
+            let is_end = i == nodes.len() - 1;
+            let is_junction = uses[&n] > 1;
+            let bends = !is_end && {
+                let next = position(&nodes[i + 1]);
+                let turn = (next.y - here.y).atan2(next.x - here.x) - (here.y - last.y).atan2(here.x - last.x);
+                let turn = (turn + PI).rem_euclid(2.0 * PI) - PI;
+                turn.abs() > MAX_BEND
+            };
+            if is_end || is_junction || (bends && last.distance(&here) >= MIN_SEGMENT) {
+                stops.push(n);
+            }
+        }
+
+        for pair in stops.windows(2) {
+            let (n0, n2) = (pair[0], pair[1]);
+            if n0 == n2 || position(&n0).distance(&position(&n2)) < 1.0 {
+                continue;
+            }
+            let mut intersection_at = |n: u64| {
+                intersections
+                    .entry(n)
+                    .or_insert_with(|| {
+                        let p = position(&n);
+                        let intersection = Arc::new(Mutex::new(Intersection::new(p.x, p.y)));
+                        map.intersections.push(intersection.clone());
+                        intersection
+                    })
+                    .clone()
+            };
+            let i0 = intersection_at(n0);
+            let i2 = intersection_at(n2);
+            map.add_road(i0, i2, profile.clone(), *kind);
+        }
+    }
+    map
+}
+
+pub fn load(path: &str) -> io::Result<Map> {
+    let data = if path.ends_with(".pbf") {
+        pbf::parse(&fs::read(path)?)
+    } else {
+        parse(&fs::read_to_string(path)?)
+    };
+    let data = data.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(to_map(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let tags = |pairs: &[(&str, &str)]| {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<String, String>>()
+        };
+
+        let (p, kind) = profile(&tags(&[("highway", "primary"), ("lanes", "4"), ("cycleway", "lane")])).unwrap();
+        assert!(kind == RoadKind::Arterial);
+        assert_eq!(p.right_lane_kinds, vec![LaneKind::Car, LaneKind::Car, LaneKind::Bike]);
+        assert_eq!(p.left_lane_kinds, vec![LaneKind::Car, LaneKind::Car, LaneKind::Bike]);
+
+        let (p, _) = profile(&tags(&[("highway", "residential"), ("oneway", "yes")])).unwrap();
+        assert_eq!(p.right_lane_kinds, vec![LaneKind::Car]);
+        assert!(p.left_lane_kinds.is_empty());
+
+        assert!(profile(&tags(&[("highway", "proposed")])).is_none());
+        assert!(profile(&tags(&[("building", "yes")])).is_none());
+    }
+
+    #[test]
+    fn test_import() {
+        // A side street joining a main road halfway
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <osm version="0.6">
+              <node id="1" lat="51.0010" lon="4.0000"/>
+              <node id="2" lat="51.0010" lon="4.0010"/>
+              <node id="3" lat="51.0010" lon="4.0020"/>
+              <node id="4" lat="51.0000" lon="4.0010"/>
+              <node id="5" lat="51.0000" lon="4.0020"><tag k="name" v="Unused"/></node>
+              <way id="10">
+                <nd ref="1"/><nd ref="2"/><nd ref="3"/>
+                <tag k="highway" v="secondary"/>
+                <tag k="name" v="Main &amp; Co"/>
+                <tag k="note" v="a > b, &#233;&#xE9; &unknown;"/>
+              </way>
+              <way id="11">
+                <nd ref="4"/><nd ref="2"/>
+                <tag k="highway" v="residential"/>
+                <tag k="oneway" v="-1"/>
+              </way>
+              <way id="12"><nd ref="3"/><nd ref="5"/><tag k="building" v="yes"/></way>
+            </osm>"#;
+        let data = parse(text).unwrap();
+        assert_eq!(data.nodes.len(), 5);
+        assert_eq!(data.ways[0].tags["name"], "Main & Co");
+        assert_eq!(data.ways[0].tags["note"], "a > b, \u{e9}\u{e9} &unknown;");
+
+        let map = to_map(&data);
+        assert_eq!(map.intersections.len(), 4);
+        assert_eq!(map.roads.len(), 3);
+
+        // About 70 meters per thousandth of a degree of longitude up here
+        let center = map.intersections[1].lock().unwrap().center;
+        assert!(center.x - MARGIN > 65.0 && center.x - MARGIN < 75.0, "{}", center.x);
+        let (lat, lon) = map.geo.unwrap().unproject(&center);
+        assert!((lat - 51.001).abs() < 1e-9 && (lon - 4.001).abs() < 1e-9);
+
+        // The side street runs from the main road to node 4
+        let road = map.roads[2].lock().unwrap();
+        assert!(road.i0.lock().unwrap().center.y < road.i2.lock().unwrap().center.y);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use cairo::Context;
+
+use crate::{agent::MIN_GAP, demand::TICKS_PER_HOUR, lane::Lane, map::{key, Map}};
+
+// Agents per hour a lane takes, a typical saturation flow
+const CAPACITY_PER_HOUR: f64 = 1800.0;
+// Share of the lane width colored
+const OVERLAY_WIDTH: f64 = 0.6;
+
+// What lanes are colored by, green being fine and red congested.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Overlay {
+    None,
+    // Agent speeds against what they could go on the lane
+    Speed,
+    // Share of the lane taken up by agents and the gaps they keep
+    Density,
+    // Agents so far against what the lane could have taken
+    Volume,
+}
+
+// Green through yellow to red as value goes from 0 to 1.
+pub fn heat_color(value: f64) -> (f64, f64, f64) {
+    let value = value.clamp(0.0, 1.0);
+    if value < 0.5 {
+        (0.20 + 1.5 * value, 0.75, 0.20)
+    } else {
+        (0.95, 0.75 - 1.3 * (value - 0.5), 0.20)
+    }
+}
+
+impl Overlay {
+    pub fn next(&self) -> Overlay {
+        match self {
+            Overlay::None => Overlay::Speed,
+            Overlay::Speed => Overlay::Density,
+            Overlay::Density => Overlay::Volume,
+            Overlay::Volume => Overlay::None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Overlay::None => "None",
+            Overlay::Speed => "Speed",
+            Overlay::Density => "Density",
+            Overlay::Volume => "Volume / capacity",
+        }
+    }
+
+    // Ends of the legend, for 0 and 1
+    fn labels(&self) -> (&'static str, &'static str) {
+        match self {
+            Overlay::None => ("", ""),
+            Overlay::Speed => ("free flow", "stopped"),
+            Overlay::Density => ("empty", "jammed"),
+            Overlay::Volume => ("0%", "100%"),
+        }
+    }
+
+    // How congested each of the map's lanes is from 0 to 1, lanes without a
+    // value are left out. Keyed by lane address.
+    pub fn values(&self, map: &Map, lanes: &[Arc<Mutex<Lane>>]) -> HashMap<usize, f64> {
+        let mut values = HashMap::new();
+        match self {
+            Overlay::None => {}
+            Overlay::Speed => {
+                let mut ratios: HashMap<usize, (f64, u32)> = HashMap::new();
+                for agent in &map.agents {
+                    let agent = agent.lock().unwrap();
+                    let max_speed = agent.l.lock().unwrap().max_speed().min(agent.top_speed);
+                    let ratio = ratios.entry(key(&agent.l)).or_insert((0.0, 0));
+                    ratio.0 += agent.speed / max_speed.max(0.01);
+                    ratio.1 += 1;
+                }
+                for (lane, (sum, count)) in ratios {
+                    values.insert(lane, 1.0 - sum / count as f64);
+                }
+            }
+            Overlay::Density | Overlay::Volume => {
+                let hours = map.tick.max(1) as f64 / TICKS_PER_HOUR as f64;
+                for lane in lanes {
+                    let value = if *self == Overlay::Density {
+                        let lane = lane.lock().unwrap();
+                        let taken: f64 = lane.occupants.iter().map(|(_, _, length)| length + MIN_GAP).sum();
+                        taken / lane.length().max(0.01)
+                    } else {
+                        map.metrics.volume(lane) as f64 / (CAPACITY_PER_HOUR * hours)
+                    };
+                    values.insert(key(lane), value);
+                }
+            }
+        }
+        values
+    }
+
+    // Color the lanes over the map.
+    pub fn draw(&self, map: &Map, context: &Context) {
+        if *self == Overlay::None {
+            return;
+        }
+        let lanes = map.lanes();
+        let values = self.values(map, &lanes);
+        context.save().expect("Failed to draw overlay!");
+        context.set_line_cap(cairo::LineCap::Butt);
+        for lane in &lanes {
+            if let Some(value) = values.get(&key(lane)) {
+                let lane = lane.lock().unwrap();
+                let (r, g, b) = heat_color(*value);
+                context.set_source_rgba(r, g, b, 0.85);
+                context.set_line_width(lane.width * OVERLAY_WIDTH);
+                context.new_path();
+                lane.curve.plot(context);
+                context.stroke().expect("Failed to draw overlay!");
+            }
+        }
+        context.restore().expect("Failed to draw overlay!");
+    }
+
+    // Legend in the bottom left corner of the window.
+    pub fn draw_legend(&self, context: &Context, height: f64) {
+        if *self == Overlay::None {
+            return;
+        }
+        let (x, y) = (8.0, height - 64.0);
+        context.set_source_rgba(0.10, 0.10, 0.10, 0.75);
+        context.rectangle(x, y, 220.0, 56.0);
+        context.fill().expect("Failed to draw legend!");
+
+        let gradient = cairo::LinearGradient::new(x + 8.0, 0.0, x + 212.0, 0.0);
+        for stop in 0..=4 {
+            let (r, g, b) = heat_color(stop as f64 / 4.0);
+            gradient.add_color_stop_rgb(stop as f64 / 4.0, r, g, b);
+        }
+        context.set_source(&gradient).expect("Failed to draw legend!");
+        context.rectangle(x + 8.0, y + 24.0, 204.0, 10.0);
+        context.fill().expect("Failed to draw legend!");
+
+        let (low, high) = self.labels();
+        context.set_source_rgb(0.95, 0.95, 0.95);
+        context.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+        context.set_font_size(12.0);
+        context.move_to(x + 8.0, y + 16.0);
+        context.show_text(self.name()).expect("Failed to draw legend!");
+        context.move_to(x + 8.0, y + 48.0);
+        context.show_text(low).expect("Failed to draw legend!");
+        let extents = context.text_extents(high).expect("Failed to draw legend!");
+        context.move_to(x + 212.0 - extents.width(), y + 48.0);
+        context.show_text(high).expect("Failed to draw legend!");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::AgentKind, network};
+
+    #[test]
+    fn test_overlay() {
+        assert_eq!(heat_color(0.0), (0.20, 0.75, 0.20));
+        assert_eq!(heat_color(2.0), heat_color(1.0));
+        assert_eq!(Overlay::Volume.next(), Overlay::None);
+
+        let mut map = network::parse(
+            "intersection 16 16\nintersection 112 16\nroad 0 1 Residential One tile: Car | Car\n",
+        )
+        .unwrap();
+        let lane = map.roads[0].lock().unwrap().lanes[0].clone();
+        map.spawn_agent(AgentKind::Car, lane.clone(), 10.0);
+        let lanes = map.lanes();
+        assert!(Overlay::None.values(&map, &lanes).is_empty());
+
+        // Standing still, one car on an otherwise empty lane
+        let speed = Overlay::Speed.values(&map, &lanes);
+        assert_eq!(speed.len(), 1);
+        assert_eq!(speed[&key(&lane)], 1.0);
+        let density = Overlay::Density.values(&map, &lanes);
+        let length = lane.lock().unwrap().length();
+        assert!((density[&key(&lane)] - (AgentKind::Car.length() + MIN_GAP) / length).abs() < 1e-9);
+        assert_eq!(density.len(), lanes.len());
+
+        // One agent in the first hour of the lane's capacity
+        map.tick = TICKS_PER_HOUR - 1;
+        map.update();
+        let volume = Overlay::Volume.values(&map, &lanes);
+        assert!((volume[&key(&lane)] - 1.0 / CAPACITY_PER_HOUR).abs() < 1e-9);
+    }
+}
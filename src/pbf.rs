@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use crate::{
+    inflate,
+    osm::{OsmData, OsmNode, OsmWay},
+};
+
+// A PBF extract is a row of blobs, each a big endian u32 length, a BlobHeader
+// message and a Blob message holding an OSMHeader or OSMData block, raw or
+// zlib compressed. Only nodes, ways and the tags of ways are read, like the
+// XML import does.
+
+// Features a reader has to support to read the file
+const FEATURES: [&str; 2] = ["OsmSchema-V0.6", "DenseNodes"];
+// Largest blob the format allows
+const MAX_BLOB: usize = 32 * 1024 * 1024;
+
+// A protobuf value, fixed size numbers read as integers too
+enum Value<'a> {
+    Number(u64),
+    Bytes(&'a [u8]),
+}
+
+fn varint(data: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).ok_or("Truncated varint")?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(String::from("Varint too long"))
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, length: usize) -> Result<&'a [u8], String> {
+    let bytes = data.get(*pos..pos.saturating_add(length)).ok_or("Truncated message")?;
+    *pos += length;
+    Ok(bytes)
+}
+
+// The fields of a message as (field number, value), in the order written.
+fn fields(data: &[u8]) -> Result<Vec<(u64, Value<'_>)>, String> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = varint(data, &mut pos)?;
+        let value = match key & 7 {
+            0 => Value::Number(varint(data, &mut pos)?),
+            1 => Value::Number(take(data, &mut pos, 8)?.iter().rev().fold(0, |n, b| n << 8 | *b as u64)),
+            5 => Value::Number(take(data, &mut pos, 4)?.iter().rev().fold(0, |n, b| n << 8 | *b as u64)),
+            2 => {
+                let length = varint(data, &mut pos)? as usize;
+                Value::Bytes(take(data, &mut pos, length)?)
+            }
+            wire_type => return Err(format!("Unsupported wire type {}", wire_type)),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+// Numbers of a repeated field, packed or one at a time
+fn numbers(value: &Value) -> Result<Vec<u64>, String> {
+    match value {
+        Value::Number(n) => Ok(vec![*n]),
+        Value::Bytes(data) => {
+            let mut numbers = Vec::new();
+            let mut pos = 0;
+            while pos < data.len() {
+                numbers.push(varint(data, &mut pos)?);
+            }
+            Ok(numbers)
+        }
+    }
+}
+
+fn zigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+// Signed numbers stored as differences from the one before
+fn deltas(numbers: &[u64]) -> Vec<i64> {
+    numbers
+        .iter()
+        .scan(0, |sum, n| {
+            *sum += zigzag(*n);
+            Some(*sum)
+        })
+        .collect()
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+// The block in a blob, inflated when needed.
+fn blob_data(blob: &[u8]) -> Result<Vec<u8>, String> {
+    for (field, value) in fields(blob)? {
+        match (field, value) {
+            (1, Value::Bytes(raw)) => return Ok(raw.to_vec()),
+            (3, Value::Bytes(data)) => return inflate::zlib(data),
+            (4, _) | (6, _) | (7, _) => return Err(String::from("Only raw and zlib blobs are supported")),
+            _ => {}
+        }
+    }
+    Err(String::from("Empty blob"))
+}
+
+fn check_header(block: &[u8]) -> Result<(), String> {
+    for (field, value) in fields(block)? {
+        if let (4, Value::Bytes(feature)) = (field, value) {
+            let feature = text(feature);
+            if !FEATURES.contains(&feature.as_str()) {
+                return Err(format!("Unsupported PBF feature {}", feature));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Nodes and ways of a PrimitiveBlock.
+fn read_block(block: &[u8], data: &mut OsmData) -> Result<(), String> {
+    let mut strings = Vec::new();
+    let mut groups = Vec::new();
+    // Coordinates are in units of granularity nanodegrees from the offsets
+    let (mut granularity, mut lat_offset, mut lon_offset) = (100, 0, 0);
+    for (field, value) in fields(block)? {
+        match (field, value) {
+            (1, Value::Bytes(table)) => {
+                for (field, value) in fields(table)? {
+                    if let (1, Value::Bytes(s)) = (field, value) {
+                        strings.push(text(s));
+                    }
+                }
+            }
+            (2, Value::Bytes(group)) => groups.push(group),
+            (17, Value::Number(n)) => granularity = n as i64,
+            (19, Value::Number(n)) => lat_offset = n as i64,
+            (20, Value::Number(n)) => lon_offset = n as i64,
+            _ => {}
+        }
+    }
+    let degrees = |offset: i64, n: i64| (offset + granularity * n) as f64 * 1e-9;
+    let string = |i: &u64| strings.get(*i as usize).cloned().ok_or(String::from("Bad string index"));
+    let node = |lat: i64, lon: i64| OsmNode { lat: degrees(lat_offset, lat), lon: degrees(lon_offset, lon) };
+
+    for group in groups {
+        for (field, value) in fields(group)? {
+            match (field, value) {
+                (1, Value::Bytes(message)) => {
+                    let (mut id, mut lat, mut lon) = (0, 0, 0);
+                    for (field, value) in fields(message)? {
+                        match (field, value) {
+                            (1, Value::Number(n)) => id = zigzag(n),
+                            (8, Value::Number(n)) => lat = zigzag(n),
+                            (9, Value::Number(n)) => lon = zigzag(n),
+                            _ => {}
+                        }
+                    }
+                    data.nodes.insert(id as u64, node(lat, lon));
+                }
+                (2, Value::Bytes(message)) => {
+                    let (mut ids, mut lats, mut lons) = (Vec::new(), Vec::new(), Vec::new());
+                    for (field, value) in fields(message)? {
+                        match field {
+                            1 => ids.extend(numbers(&value)?),
+                            8 => lats.extend(numbers(&value)?),
+                            9 => lons.extend(numbers(&value)?),
+                            _ => {}
+                        }
+                    }
+                    if lats.len() != ids.len() || lons.len() != ids.len() {
+                        return Err(String::from("Dense nodes without coordinates"));
+                    }
+                    for ((id, lat), lon) in deltas(&ids).into_iter().zip(deltas(&lats)).zip(deltas(&lons)) {
+                        data.nodes.insert(id as u64, node(lat, lon));
+                    }
+                }
+                (3, Value::Bytes(message)) => {
+                    let (mut keys, mut values, mut refs) = (Vec::new(), Vec::new(), Vec::new());
+                    for (field, value) in fields(message)? {
+                        match field {
+                            2 => keys.extend(numbers(&value)?),
+                            3 => values.extend(numbers(&value)?),
+                            8 => refs.extend(numbers(&value)?),
+                            _ => {}
+                        }
+                    }
+                    if keys.len() != values.len() {
+                        return Err(String::from("Way tags without values"));
+                    }
+                    let mut tags = HashMap::new();
+                    for (key, value) in keys.iter().zip(&values) {
+                        tags.insert(string(key)?, string(value)?);
+                    }
+                    let nodes = deltas(&refs).into_iter().map(|n| n as u64).collect();
+                    data.ways.push(OsmWay { nodes, tags });
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn parse(bytes: &[u8]) -> Result<OsmData, String> {
+    let mut data = OsmData { nodes: HashMap::new(), ways: Vec::new() };
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let length = take(bytes, &mut pos, 4)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let header = take(bytes, &mut pos, length)?;
+        let (mut kind, mut size) = (String::new(), 0);
+        for (field, value) in fields(header)? {
+            match (field, value) {
+                (1, Value::Bytes(k)) => kind = text(k),
+                (3, Value::Number(n)) => size = n as usize,
+                _ => {}
+            }
+        }
+        if size > MAX_BLOB {
+            return Err(format!("Blob of {} bytes is too large", size));
+        }
+        let blob = take(bytes, &mut pos, size)?;
+        match kind.as_str() {
+            "OSMHeader" => check_header(&blob_data(blob)?)?,
+            "OSMData" => read_block(&blob_data(blob)?, &mut data)?,
+            // Others may be skipped
+            _ => {}
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm::to_map;
+
+    fn put_varint(bytes: &mut Vec<u8>, mut n: u64) {
+        while n >= 0x80 {
+            bytes.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        bytes.push(n as u8);
+    }
+
+    fn number(field: u64, n: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        put_varint(&mut bytes, field << 3);
+        put_varint(&mut bytes, n);
+        bytes
+    }
+
+    fn message(field: u64, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        put_varint(&mut bytes, field << 3 | 2);
+        put_varint(&mut bytes, data.len() as u64);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn packed(field: u64, numbers: &[u64]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for n in numbers {
+            put_varint(&mut data, *n);
+        }
+        message(field, &data)
+    }
+
+    fn signed(n: i64) -> u64 {
+        ((n << 1) ^ (n >> 63)) as u64
+    }
+
+    // A blob of the kind, the data wrapped in a stored zlib block or raw
+    fn blob(kind: &str, data: &[u8], zlib: bool) -> Vec<u8> {
+        let blob = if zlib {
+            let mut stream = vec![0x78, 0x01, 0x01];
+            stream.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            stream.extend_from_slice(&(!(data.len() as u16)).to_le_bytes());
+            stream.extend_from_slice(data);
+            stream.extend_from_slice(&inflate::adler32(data).to_be_bytes());
+            [number(2, data.len() as u64), message(3, &stream)].concat()
+        } else {
+            message(1, data)
+        };
+        let header = [message(1, kind.as_bytes()), number(3, blob.len() as u64)].concat();
+        [(header.len() as u32).to_be_bytes().to_vec(), header, blob].concat()
+    }
+
+    #[test]
+    fn test_pbf() {
+        let header = [message(4, b"OsmSchema-V0.6"), message(4, b"DenseNodes"), message(16, b"test")].concat();
+
+        // A main road through nodes 1 to 3 and a side street from node 4
+        let strings: Vec<u8> = ["", "highway", "secondary", "residential", "name", "Main"]
+            .iter()
+            .flat_map(|s| message(1, s.as_bytes()))
+            .collect();
+        let dense = [
+            packed(1, &[signed(1), signed(1), signed(1), signed(1)]),
+            packed(8, &[signed(10), signed(0), signed(0), signed(-10)]),
+            packed(9, &[signed(0), signed(10), signed(10), signed(-10)]),
+        ]
+        .concat();
+        let main = [number(1, 10), packed(2, &[1, 4]), packed(3, &[2, 5]), packed(8, &[signed(1), signed(1), signed(1)])]
+            .concat();
+        let side = [number(1, 11), packed(2, &[1]), packed(3, &[3]), packed(8, &[signed(4), signed(-2)])].concat();
+        let group = [message(2, &dense), message(3, &main), message(3, &side)].concat();
+        let block = [
+            message(1, &strings),
+            message(2, &group),
+            number(17, 100_000),
+            // Offsets are plain int64, not zigzag coded
+            number(19, 51_000_000_000),
+            number(20, 4_000_000_000),
+        ]
+        .concat();
+        let file = [blob("OSMHeader", &header, false), blob("OSMData", &block, true)].concat();
+
+        let data = parse(&file).unwrap();
+        assert_eq!(data.nodes.len(), 4);
+        assert!((data.nodes[&3].lat - 51.001).abs() < 1e-9 && (data.nodes[&3].lon - 4.002).abs() < 1e-9);
+        assert!((data.nodes[&4].lat - 51.0).abs() < 1e-9);
+        assert_eq!(data.ways.len(), 2);
+        assert_eq!(data.ways[0].nodes, [1, 2, 3]);
+        assert_eq!(data.ways[0].tags["name"], "Main");
+        assert_eq!(data.ways[1].nodes, [4, 2]);
+
+        let map = to_map(&data);
+        assert_eq!(map.intersections.len(), 4);
+        assert_eq!(map.roads.len(), 3);
+
+        // Features we don't know make the file unreadable
+        let header = message(4, b"HistoricalInformation");
+        assert!(parse(&blob("OSMHeader", &header, false)).is_err());
+        assert!(parse(&file[..file.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use std::sync::{Arc, Mutex};
+
+use gtk4::{
+    prelude::*, Button, ComboBoxText, DrawingArea, Entry, Label, ListBox, Orientation, Window,
+};
+
+use crate::{lane::LaneKind, road_profile::RoadProfile, PROFILES_FILE};
+
+type Profiles = Arc<Mutex<Vec<Arc<Mutex<RoadProfile>>>>>;
+
+const PREVIEW_SCALE: f64 = 4.0;
+
+#[derive(Copy, Clone)]
+enum Side {
+    Right,
+    Left,
+}
+
+#[derive(Copy, Clone)]
+enum LaneEdit {
+    Add,
+    Set,
+    Up,
+    Down,
+    Remove,
+}
+
+fn side_lanes(profile: &mut RoadProfile, side: Side) -> &mut Vec<LaneKind> {
+    match side {
+        Side::Right => &mut profile.right_lane_kinds,
+        Side::Left => &mut profile.left_lane_kinds,
+    }
+}
+
+// Apply an edit to the lanes of one side, returns the row to select afterwards.
+fn edit_lanes(
+    lanes: &mut Vec<LaneKind>,
+    edit: LaneEdit,
+    row: Option<usize>,
+    kind: LaneKind,
+) -> Option<usize> {
+    match (edit, row) {
+        (LaneEdit::Add, Some(row)) if row < lanes.len() => {
+            lanes.insert(row + 1, kind);
+            Some(row + 1)
+        }
+        (LaneEdit::Add, _) => {
+            lanes.push(kind);
+            Some(lanes.len() - 1)
+        }
+        (LaneEdit::Set, Some(row)) if row < lanes.len() => {
+            lanes[row] = kind;
+            Some(row)
+        }
+        (LaneEdit::Up, Some(row)) if row > 0 && row < lanes.len() => {
+            lanes.swap(row, row - 1);
+            Some(row - 1)
+        }
+        (LaneEdit::Down, Some(row)) if row + 1 < lanes.len() => {
+            lanes.swap(row, row + 1);
+            Some(row + 1)
+        }
+        (LaneEdit::Remove, Some(row)) if row < lanes.len() => {
+            lanes.remove(row);
+            if lanes.is_empty() {
+                None
+            } else {
+                Some(row.min(lanes.len() - 1))
+            }
+        }
+        _ => row,
+    }
+}
+
+fn selected_profile(
+    profiles: &Profiles,
+    selected: &Arc<Mutex<usize>>,
+) -> Option<Arc<Mutex<RoadProfile>>> {
+    let index = *selected.lock().unwrap();
+    profiles.lock().unwrap().get(index).cloned()
+}
+
+fn fill_lanes(list: &ListBox, lanes: &[LaneKind]) {
+    while let Some(child) = list.first_child() {
+        list.remove(&child);
+    }
+    for lane in lanes {
+        list.append(&Label::new(Some(lane.name())));
+    }
+}
+
+fn fill_profiles(combo: &ComboBoxText, profiles: &Profiles, selected: usize) {
+    let names: Vec<String> = profiles
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|p| p.lock().unwrap().name.clone())
+        .collect();
+    combo.remove_all();
+    for name in &names {
+        combo.append_text(name);
+    }
+    if !names.is_empty() {
+        combo.set_active(Some(selected.min(names.len() - 1) as u32));
+    }
+}
+
+// The lanes of one side, from the center of the road and outwards.
+fn lane_column(
+    title: &str,
+    side: Side,
+    profiles: &Profiles,
+    selected: &Arc<Mutex<usize>>,
+    preview: &DrawingArea,
+) -> (gtk4::Box, ListBox) {
+    let column = gtk4::Box::new(Orientation::Vertical, 4);
+    column.set_hexpand(true);
+    column.append(&Label::new(Some(title)));
+
+    let list = ListBox::new();
+    list.set_vexpand(true);
+    column.append(&list);
+
+    let kinds = ComboBoxText::new();
+    for kind in LaneKind::ALL {
+        kinds.append_text(kind.name());
+    }
+    kinds.set_active(Some(0));
+    column.append(&kinds);
+
+    let buttons = gtk4::Box::new(Orientation::Horizontal, 4);
+    let edits = [
+        ("Add", LaneEdit::Add),
+        ("Set", LaneEdit::Set),
+        ("Up", LaneEdit::Up),
+        ("Down", LaneEdit::Down),
+        ("Remove", LaneEdit::Remove),
+    ];
+    for (label, edit) in edits {
+        let button = Button::with_label(label);
+        let profiles = profiles.clone();
+        let selected = selected.clone();
+        let list = list.clone();
+        let kinds = kinds.clone();
+        let preview = preview.clone();
+        button.connect_clicked(move |_| {
+            let profile = match selected_profile(&profiles, &selected) {
+                Some(profile) => profile,
+                None => return,
+            };
+            let row = list.selected_row().map(|r| r.index() as usize);
+            let kind = LaneKind::ALL[kinds.active().unwrap_or(0) as usize];
+
+            let mut profile_lock = profile.lock().unwrap();
+            let lanes = side_lanes(&mut profile_lock, side);
+            let row = edit_lanes(lanes, edit, row, kind);
+            let lanes = lanes.clone();
+            drop(profile_lock);
+
+            fill_lanes(&list, &lanes);
+            if let Some(row) = row {
+                list.select_row(list.row_at_index(row as i32).as_ref());
+            }
+            preview.queue_draw();
+        });
+        buttons.append(&button);
+    }
+    column.append(&buttons);
+
+    (column, list)
+}
+
+// Dialog to add, reorder and remove the lanes of the road profiles.
+pub fn open(parent: &impl IsA<Window>, profiles: Profiles, profile: usize) {
+    let window = Window::builder()
+        .title("Road Profiles")
+        .transient_for(parent)
+        .modal(true)
+        .default_width(480)
+        .default_height(480)
+        .build();
+    let selected = Arc::new(Mutex::new(profile));
+    let content = gtk4::Box::new(Orientation::Vertical, 6);
+
+    // Profile selection
+    let header = gtk4::Box::new(Orientation::Horizontal, 4);
+    let combo = ComboBoxText::new();
+    combo.set_hexpand(true);
+    let new_button = Button::with_label("New");
+    let delete_button = Button::with_label("Delete");
+    header.append(&combo);
+    header.append(&new_button);
+    header.append(&delete_button);
+    content.append(&header);
+
+    let name = Entry::new();
+    content.append(&name);
+
+    // Cross-section preview
+    let preview = DrawingArea::new();
+    preview.set_content_height(120);
+    {
+        let profiles = profiles.clone();
+        let selected = selected.clone();
+        preview.set_draw_func(move |_, context, width, height| {
+            context.set_source_rgb(0.36, 0.55, 0.35);
+            context.paint().expect("omg!");
+            if let Some(profile) = selected_profile(&profiles, &selected) {
+                context.scale(PREVIEW_SCALE, PREVIEW_SCALE);
+                context.set_line_width(1.0 / PREVIEW_SCALE);
+                profile.lock().unwrap().draw_cross_section(
+                    context,
+                    width as f64 / 2.0 / PREVIEW_SCALE,
+                    0.0,
+                    height as f64 / PREVIEW_SCALE,
+                );
+            }
+        });
+    }
+
+    let sides = gtk4::Box::new(Orientation::Horizontal, 12);
+    let (left_column, left_list) = lane_column("Left", Side::Left, &profiles, &selected, &preview);
+    let (right_column, right_list) =
+        lane_column("Right", Side::Right, &profiles, &selected, &preview);
+    sides.append(&left_column);
+    sides.append(&right_column);
+    content.append(&sides);
+    content.append(&preview);
+
+    let save_button = Button::with_label("Save");
+    content.append(&save_button);
+
+    // Show the selected profile
+    {
+        let profiles = profiles.clone();
+        let selected = selected.clone();
+        let name = name.clone();
+        let preview = preview.clone();
+        combo.connect_changed(move |combo| {
+            let index = match combo.active() {
+                Some(index) => index as usize,
+                None => return,
+            };
+            *selected.lock().unwrap() = index;
+            let profile = match selected_profile(&profiles, &selected) {
+                Some(profile) => profile,
+                None => return,
+            };
+            let profile_lock = profile.lock().unwrap();
+            let profile_name = profile_lock.name.clone();
+            let left = profile_lock.left_lane_kinds.clone();
+            let right = profile_lock.right_lane_kinds.clone();
+            drop(profile_lock);
+
+            name.set_text(&profile_name);
+            fill_lanes(&left_list, &left);
+            fill_lanes(&right_list, &right);
+            preview.queue_draw();
+        });
+    }
+
+    // Rename
+    {
+        let profiles = profiles.clone();
+        let selected = selected.clone();
+        name.connect_changed(move |name| {
+            // ':' and '|' would break the profiles file
+            let text = RoadProfile::valid_name(&name.text());
+            if text != name.text().as_str() {
+                name.set_text(&text);
+                return;
+            }
+            if let Some(profile) = selected_profile(&profiles, &selected) {
+                profile.lock().unwrap().name = text;
+            }
+        });
+    }
+
+    // New profile, a single car lane each way
+    {
+        let profiles = profiles.clone();
+        let combo = combo.clone();
+        new_button.connect_clicked(move |_| {
+            let mut profiles_lock = profiles.lock().unwrap();
+            let mut profile = RoadProfile::new();
+            profile.name = format!("Profile {}", profiles_lock.len() + 1);
+            profile.right_lane_kinds.push(LaneKind::Car);
+            profile.left_lane_kinds.push(LaneKind::Car);
+            profiles_lock.push(Arc::new(Mutex::new(profile)));
+            let index = profiles_lock.len() - 1;
+            drop(profiles_lock);
+
+            fill_profiles(&combo, &profiles, index);
+        });
+    }
+
+    // Delete profile, but keep at least one
+    {
+        let profiles = profiles.clone();
+        let selected = selected.clone();
+        let combo = combo.clone();
+        delete_button.connect_clicked(move |_| {
+            let index = *selected.lock().unwrap();
+            let mut profiles_lock = profiles.lock().unwrap();
+            if profiles_lock.len() > 1 && index < profiles_lock.len() {
+                profiles_lock.remove(index);
+            }
+            drop(profiles_lock);
+
+            fill_profiles(&combo, &profiles, index.saturating_sub(1));
+        });
+    }
+
+    // Save all profiles
+    {
+        let profiles = profiles.clone();
+        let selected = selected.clone();
+        let combo = combo.clone();
+        save_button.connect_clicked(move |_| {
+            let profiles_lock = profiles.lock().unwrap();
+            let locks: Vec<_> = profiles_lock.iter().map(|p| p.lock().unwrap()).collect();
+            let refs: Vec<&RoadProfile> = locks.iter().map(|p| &**p).collect();
+            match RoadProfile::save_all(PROFILES_FILE, &refs) {
+                Ok(()) => println!("Saved {} profiles to {}", refs.len(), PROFILES_FILE),
+                Err(e) => println!("Failed to save profiles: {}", e),
+            }
+            drop(refs);
+            drop(locks);
+            drop(profiles_lock);
+
+            // Names might have changed
+            let index = *selected.lock().unwrap();
+            fill_profiles(&combo, &profiles, index);
+        });
+    }
+
+    fill_profiles(&combo, &profiles, profile);
+    window.set_child(Some(&content));
+    window.present();
+}
@@ -1,7 +1,10 @@
+use std::sync::{Arc, Mutex};
+
 use cairo::Context;
 
-use crate::node::Node;
+use crate::{lane::Lane, node::Node};
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum PropertyKind {
     Vacant,
     Residential,
@@ -9,12 +12,46 @@ pub enum PropertyKind {
     Industrial,
 }
 
+impl PropertyKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PropertyKind::Vacant => "Vacant",
+            PropertyKind::Residential => "Residential",
+            PropertyKind::Commercial => "Commercial",
+            PropertyKind::Industrial => "Industrial",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PropertyKind> {
+        [PropertyKind::Vacant, PropertyKind::Residential, PropertyKind::Commercial, PropertyKind::Industrial]
+            .iter()
+            .copied()
+            .find(|k| k.name() == name)
+    }
+
+    pub fn next(&self) -> PropertyKind {
+        match self {
+            PropertyKind::Vacant => PropertyKind::Residential,
+            PropertyKind::Residential => PropertyKind::Commercial,
+            PropertyKind::Commercial => PropertyKind::Industrial,
+            PropertyKind::Industrial => PropertyKind::Vacant,
+        }
+    }
+
+    // Where people go to work and shop
+    pub fn is_destination(&self) -> bool {
+        matches!(self, PropertyKind::Commercial | PropertyKind::Industrial)
+    }
+}
+
 pub struct Property {
-    kind: PropertyKind,
-    n0: Node,
-    n1: Node,
-    n2: Node,
-    n3: Node,
+    pub kind: PropertyKind,
+    pub n0: Node,
+    pub n1: Node,
+    pub n2: Node,
+    pub n3: Node,
+    // Driveway onto the road, as (lane, distance)
+    pub access: Option<(Arc<Mutex<Lane>>, f64)>,
 }
 
 impl Property {
@@ -25,9 +62,27 @@ impl Property {
             n1,
             n2,
             n3,
+            access: None,
         }
     }
 
+    pub fn center(&self) -> Node {
+        Node::new(
+            (self.n0.x + self.n1.x + self.n2.x + self.n3.x) / 4.0,
+            (self.n0.y + self.n1.y + self.n2.y + self.n3.y) / 4.0,
+        )
+    }
+
+    // Is n inside the plot? Plots are convex.
+    pub fn contains(&self, n: &Node) -> bool {
+        let corners = [self.n0, self.n1, self.n2, self.n3];
+        let mut sides = corners.iter().zip(corners.iter().cycle().skip(1)).map(|(a, b)| {
+            (b.x - a.x) * (n.y - a.y) - (b.y - a.y) * (n.x - a.x)
+        });
+        let first = sides.next().unwrap();
+        sides.all(|side| side * first >= 0.0)
+    }
+
     pub fn draw(&self, context: &Context) {
         match self.kind {
             PropertyKind::Residential => {
@@ -53,3 +108,22 @@ impl Property {
         context.fill().expect("OMG!");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains() {
+        let property = Property::new(
+            PropertyKind::Vacant,
+            Node::new(0.0, 0.0),
+            Node::new(0.0, 10.0),
+            Node::new(10.0, 10.0),
+            Node::new(10.0, 0.0),
+        );
+        assert!(property.contains(&Node::new(5.0, 5.0)));
+        assert!(!property.contains(&Node::new(15.0, 5.0)));
+        assert!(property.center().distance(&Node::new(5.0, 5.0)) < 0.01);
+    }
+}
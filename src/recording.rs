@@ -0,0 +1,407 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use cairo::Context;
+
+use crate::{
+    agent::{draw_agent, AgentKind},
+    map::Map,
+    network,
+    node::Node,
+    overlay::Overlay,
+    SCALE,
+};
+
+// A recording starts with these, then events follow until the end of the
+// file. Numbers are little endian.
+//   edit: 0, what (u32 length, UTF-8), network text (u32 length, UTF-8)
+//   tick: 1, tick (u64), agent count (u32), per agent
+//         id (u32), kind (u8), x (f32), y (f32), heading (f32), speed (f32)
+const MAGIC: &[u8; 4] = b"RREC";
+const VERSION: u8 = 2;
+const EDIT: u8 = 0;
+const TICK: u8 = 1;
+
+// Height of the timeline along the bottom of the window, in pixels
+const TIMELINE_HEIGHT: f64 = 24.0;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct AgentState {
+    pub id: u32,
+    pub kind: AgentKind,
+    // Where the agent is and which way it faces, so no lanes need matching up
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+    pub speed: f32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Event {
+    // The network as saved by network::to_text after an edit
+    Edit { what: String, network: String },
+    Tick { tick: u64, agents: Vec<AgentState> },
+}
+
+fn write_text(writer: &mut impl Write, text: &str) -> io::Result<()> {
+    writer.write_all(&(text.len() as u32).to_le_bytes())?;
+    writer.write_all(text.as_bytes())
+}
+
+pub fn write_event(writer: &mut impl Write, event: &Event) -> io::Result<()> {
+    match event {
+        Event::Edit { what, network } => {
+            writer.write_all(&[EDIT])?;
+            write_text(writer, what)?;
+            write_text(writer, network)
+        }
+        Event::Tick { tick, agents } => {
+            writer.write_all(&[TICK])?;
+            writer.write_all(&tick.to_le_bytes())?;
+            writer.write_all(&(agents.len() as u32).to_le_bytes())?;
+            for agent in agents {
+                writer.write_all(&agent.id.to_le_bytes())?;
+                writer.write_all(&[agent.kind as u8])?;
+                writer.write_all(&agent.x.to_le_bytes())?;
+                writer.write_all(&agent.y.to_le_bytes())?;
+                writer.write_all(&agent.heading.to_le_bytes())?;
+                writer.write_all(&agent.speed.to_le_bytes())?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_text(reader: &mut impl Read) -> io::Result<String> {
+    let length = u32::from_le_bytes(read_bytes(reader)?) as usize;
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < length {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    String::from_utf8(bytes).map_err(|_| invalid_data("text is not UTF-8"))
+}
+
+// The next event, None at the end of the recording.
+pub fn read_event(reader: &mut impl Read) -> io::Result<Option<Event>> {
+    let mut tag = [0];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    match tag[0] {
+        EDIT => {
+            let what = read_text(reader)?;
+            let network = read_text(reader)?;
+            Ok(Some(Event::Edit { what, network }))
+        }
+        TICK => {
+            let tick = u64::from_le_bytes(read_bytes(reader)?);
+            let count = u32::from_le_bytes(read_bytes(reader)?);
+            let mut agents = Vec::new();
+            for _ in 0..count {
+                let id = u32::from_le_bytes(read_bytes(reader)?);
+                let [kind] = read_bytes(reader)?;
+                let kind = *AgentKind::ALL.get(kind as usize).ok_or_else(|| invalid_data("unknown agent kind"))?;
+                let x = f32::from_le_bytes(read_bytes(reader)?);
+                let y = f32::from_le_bytes(read_bytes(reader)?);
+                let heading = f32::from_le_bytes(read_bytes(reader)?);
+                let speed = f32::from_le_bytes(read_bytes(reader)?);
+                agents.push(AgentState { id, kind, x, y, heading, speed });
+            }
+            Ok(Some(Event::Tick { tick, agents }))
+        }
+        _ => Err(invalid_data("unknown event")),
+    }
+}
+
+pub fn read_events(reader: &mut impl Read) -> io::Result<Vec<Event>> {
+    if &read_bytes::<4>(reader)? != MAGIC {
+        return Err(invalid_data("not a recording"));
+    }
+    let [version] = read_bytes(reader)?;
+    if version != VERSION {
+        return Err(invalid_data("unsupported recording version"));
+    }
+    let mut events = Vec::new();
+    while let Some(event) = read_event(reader)? {
+        events.push(event);
+    }
+    Ok(events)
+}
+
+// Writes a run to a file as it happens.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    // Network recorded last
+    network: String,
+}
+
+impl Recorder {
+    pub fn create(path: &str, map: &Map) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        let mut recorder = Recorder { writer, network: String::new() };
+        recorder.edit(map, "start")?;
+        Ok(recorder)
+    }
+
+    // Record the network if it changed since last time.
+    pub fn edit(&mut self, map: &Map, what: &str) -> io::Result<()> {
+        let network = network::to_text(map);
+        if network == self.network {
+            return Ok(());
+        }
+        write_event(&mut self.writer, &Event::Edit { what: String::from(what), network: network.clone() })?;
+        self.network = network;
+        Ok(())
+    }
+
+    pub fn tick(&mut self, map: &Map) -> io::Result<()> {
+        let mut agents = Vec::new();
+        for agent in &map.agents {
+            let agent = agent.lock().unwrap();
+            agents.push(AgentState {
+                id: agent.id as u32,
+                kind: agent.kind,
+                x: agent.c.x as f32,
+                y: agent.c.y as f32,
+                heading: agent.heading as f32,
+                speed: agent.speed as f32,
+            });
+        }
+        write_event(&mut self.writer, &Event::Tick { tick: map.tick, agents })
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// A recorded network and the edit that led to it
+struct Network {
+    what: String,
+    map: Map,
+}
+
+struct Frame {
+    tick: u64,
+    network: usize,
+    agents: Vec<AgentState>,
+}
+
+// Plays a recording back, frame by frame.
+pub struct Replay {
+    networks: Vec<Network>,
+    frames: Vec<Frame>,
+    pub position: usize,
+    pub paused: bool,
+}
+
+impl Replay {
+    pub fn from_events(events: Vec<Event>) -> Result<Replay, String> {
+        let mut networks = Vec::new();
+        let mut frames = Vec::new();
+        for event in events {
+            match event {
+                Event::Edit { what, network } => {
+                    let map = network::parse(&network)?;
+                    networks.push(Network { what, map });
+                }
+                Event::Tick { tick, agents } => {
+                    let network = networks.len().checked_sub(1).ok_or("tick before any network")?;
+                    frames.push(Frame { tick, network, agents });
+                }
+            }
+        }
+        if frames.is_empty() {
+            return Err(String::from("no ticks recorded"));
+        }
+        Ok(Replay { networks, frames, position: 0, paused: false })
+    }
+
+    pub fn load(path: &str) -> io::Result<Replay> {
+        let events = read_events(&mut BufReader::new(File::open(path)?))?;
+        Replay::from_events(events).map_err(|e| invalid_data(&e))
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.frames[self.position].tick
+    }
+
+    pub fn agents(&self) -> &[AgentState] {
+        &self.frames[self.position].agents
+    }
+
+    // Play on, unless paused or at the end.
+    pub fn update(&mut self) {
+        if !self.paused {
+            self.position = (self.position + 1).min(self.frames.len() - 1);
+        }
+    }
+
+    // Pause and move frames forward, or back when negative.
+    pub fn step(&mut self, frames: isize) {
+        self.paused = true;
+        let position = self.position as isize + frames;
+        self.position = position.clamp(0, self.frames.len() as isize - 1) as usize;
+    }
+
+    // Jump to a point on the timeline, 0 being the start and 1 the end.
+    pub fn seek(&mut self, fraction: f64) {
+        let last = self.frames.len() - 1;
+        self.position = (fraction.clamp(0.0, 1.0) * last as f64).round() as usize;
+    }
+
+    pub fn draw(&self, context: &Context, width: f64, height: f64) {
+        let frame = &self.frames[self.position];
+        let network = &self.networks[frame.network];
+
+        context.save().expect("Failed to draw replay!");
+        context.scale(SCALE, SCALE);
+        context.set_line_width(1.0 / SCALE);
+        network.map.draw(context, Overlay::None);
+        for agent in &frame.agents {
+            let c = Node::new(agent.x as f64, agent.y as f64);
+            draw_agent(context, agent.kind, &c, agent.heading as f64, agent.kind.color());
+        }
+        context.restore().expect("Failed to draw replay!");
+
+        // Timeline, with a mark wherever the network was edited
+        let top = height - TIMELINE_HEIGHT;
+        let x_at = |position: usize| width * position as f64 / (self.frames.len() - 1).max(1) as f64;
+        context.set_source_rgba(0.10, 0.10, 0.10, 0.75);
+        context.rectangle(0.0, top, width, TIMELINE_HEIGHT);
+        context.fill().expect("Failed to draw timeline!");
+        context.set_source_rgba(0.42, 0.45, 0.83, 0.75);
+        context.rectangle(0.0, top, x_at(self.position), TIMELINE_HEIGHT);
+        context.fill().expect("Failed to draw timeline!");
+        context.set_source_rgb(0.95, 0.55, 0.10);
+        for (position, pair) in self.frames.windows(2).enumerate() {
+            if pair[0].network != pair[1].network {
+                context.move_to(x_at(position + 1), top);
+                context.line_to(x_at(position + 1), height);
+            }
+        }
+        context.stroke().expect("Failed to draw timeline!");
+
+        context.set_source_rgb(0.95, 0.95, 0.95);
+        context.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+        context.set_font_size(12.0);
+        context.move_to(8.0, height - 8.0);
+        let state = if self.paused { "paused" } else { "playing" };
+        let text = format!("Replay tick {}, {} agents, after {}, {}", frame.tick, frame.agents.len(), network.what, state);
+        context.show_text(&text).expect("Failed to draw timeline!");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::property::PropertyKind;
+
+    #[test]
+    fn test_recording() {
+        let mut map = network::parse(
+            "intersection 16 16\nintersection 96 16\nroad 0 1 Residential One tile: Car | Car\n",
+        )
+        .unwrap();
+        let lane = map.roads[0].lock().unwrap().lanes[0].clone();
+        map.spawn_agent(AgentKind::Car, lane, 5.0);
+
+        let path = std::env::temp_dir().join(format!("roads-recording-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut recorder = Recorder::create(path, &map).unwrap();
+        for _ in 0..10 {
+            map.update();
+            recorder.tick(&map).unwrap();
+        }
+        // Unchanged networks aren't recorded again
+        recorder.edit(&map, "nothing").unwrap();
+        let center = {
+            let road = map.roads[0].lock().unwrap();
+            let property = &road.properties[0];
+            Node::new((property.n0.x + property.n2.x) / 2.0, (property.n0.y + property.n2.y) / 2.0)
+        };
+        assert!(map.zone_at(&center, PropertyKind::Commercial));
+        recorder.edit(&map, "zone").unwrap();
+        map.update();
+        recorder.tick(&map).unwrap();
+        recorder.finish().unwrap();
+
+        let events = read_events(&mut BufReader::new(File::open(path).unwrap())).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(events.iter().filter(|e| matches!(e, Event::Edit { .. })).count(), 2);
+        assert_eq!(events.len(), 13);
+
+        // The agent comes back where it was, over the edited network
+        let mut replay = Replay::from_events(events).unwrap();
+        replay.seek(1.0);
+        assert_eq!(replay.tick(), 11);
+        assert_eq!(replay.networks[replay.frames[replay.position].network].what, "zone");
+        let state = &replay.agents()[0];
+        let agent = map.agents[0].lock().unwrap();
+        assert!(Node::new(state.x as f64, state.y as f64).distance(&agent.c) < 0.01);
+        assert_eq!((state.heading, state.speed), (agent.heading as f32, agent.speed as f32));
+    }
+
+    #[test]
+    fn test_timeline() {
+        let network = String::from("intersection 16 16\n");
+        let tick = |tick| Event::Tick { tick, agents: vec![] };
+        let events = vec![Event::Edit { what: String::from("start"), network }, tick(1), tick(2), tick(3)];
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        for event in &events {
+            write_event(&mut bytes, event).unwrap();
+        }
+        assert_eq!(read_events(&mut &bytes[..]).unwrap(), events);
+        assert!(read_events(&mut &bytes[..bytes.len() - 1]).is_err());
+
+        let mut replay = Replay::from_events(events).unwrap();
+        replay.update();
+        assert_eq!(replay.tick(), 2);
+        replay.step(-5);
+        assert_eq!((replay.tick(), replay.paused), (1, true));
+        replay.update();
+        assert_eq!(replay.tick(), 1);
+        replay.seek(1.0);
+        assert_eq!(replay.tick(), 3);
+        replay.step(1);
+        assert_eq!(replay.tick(), 3);
+        assert!(Replay::from_events(vec![tick(1)]).is_err());
+    }
+
+    #[test]
+    fn test_agent_kinds() {
+        let agents: Vec<AgentState> = AgentKind::ALL
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| AgentState {
+                id: i as u32,
+                kind: *kind,
+                x: i as f32 * 8.0,
+                y: -3.5,
+                heading: 1.25,
+                speed: 0.5,
+            })
+            .collect();
+        let event = Event::Tick { tick: 7, agents };
+        let mut bytes = Vec::new();
+        write_event(&mut bytes, &event).unwrap();
+        assert_eq!(read_event(&mut &bytes[..]).unwrap(), Some(event));
+    }
+}
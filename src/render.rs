@@ -0,0 +1,248 @@
+use std::{fs::File, io};
+
+use cairo::{Context, Format, ImageSurface, PdfSurface, SvgSurface};
+
+use crate::{map::{Map, BACKGROUND}, overlay::Overlay, TILE};
+
+// Room left around the network when exporting all of it
+const MARGIN: f64 = TILE * 2.0;
+
+// Part of the map to export, in map units.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Region {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Region {
+    // Given as "x,y,width,height".
+    pub fn parse(text: &str) -> Result<Region, String> {
+        let numbers: Vec<f64> = text
+            .split(',')
+            .map(|n| n.trim().parse::<f64>().map_err(|_| format!("Bad number \"{}\" in region", n)))
+            .collect::<Result<_, _>>()?;
+        match numbers[..] {
+            [x, y, width, height] if width > 0.0 && height > 0.0 => Ok(Region { x, y, width, height }),
+            [_, _, _, _] => Err(String::from("Region needs a positive width and height")),
+            _ => Err(format!("Expected x,y,width,height, got \"{}\"", text)),
+        }
+    }
+
+    // Spanned by two opposite corners.
+    pub fn from_corners(x0: f64, y0: f64, x1: f64, y1: f64) -> Region {
+        Region { x: x0.min(x1), y: y0.min(y1), width: (x1 - x0).abs(), height: (y1 - y0).abs() }
+    }
+
+    // Everything on the map, with some room around it.
+    pub fn extent(map: &Map) -> Option<Region> {
+        let mut points = Vec::new();
+        for intersection in &map.intersections {
+            points.push(intersection.lock().unwrap().center);
+        }
+        for road in &map.roads {
+            for lane in &road.lock().unwrap().lanes {
+                let lane = lane.lock().unwrap();
+                points.push(lane.curve.n0);
+                points.push(lane.curve.n1);
+            }
+        }
+        if points.is_empty() {
+            return None;
+        }
+        let x0 = points.iter().map(|n| n.x).fold(f64::MAX, f64::min) - MARGIN;
+        let y0 = points.iter().map(|n| n.y).fold(f64::MAX, f64::min) - MARGIN;
+        let x1 = points.iter().map(|n| n.x).fold(f64::MIN, f64::max) + MARGIN;
+        let y1 = points.iter().map(|n| n.y).fold(f64::MIN, f64::max) + MARGIN;
+        Some(Region::from_corners(x0, y0, x1, y1))
+    }
+}
+
+fn cairo_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+// Draw the region of the map, scale being surface units per map unit.
+pub fn draw(map: &Map, context: &Context, region: &Region, scale: f64) {
+    // Images are opaque whatever the map draws
+    let (r, g, b) = BACKGROUND;
+    context.set_source_rgb(r, g, b);
+    context.paint().expect("Failed to draw background!");
+    context.scale(scale, scale);
+    context.translate(-region.x, -region.y);
+    context.set_line_width(1.0 / scale);
+    map.draw(context, Overlay::None);
+}
+
+// The region drawn into a new image, scale pixels per map unit.
+pub fn to_image(map: &Map, region: &Region, scale: f64) -> io::Result<ImageSurface> {
+    let width = (region.width * scale).ceil() as i32;
+    let height = (region.height * scale).ceil() as i32;
+    let surface = ImageSurface::create(Format::ARgb32, width, height).map_err(cairo_error)?;
+    {
+        let context = Context::new(&surface).map_err(cairo_error)?;
+        draw(map, &context, region, scale);
+    }
+    surface.flush();
+    Ok(surface)
+}
+
+// The region as PNG, SVG or PDF going by the extension of path. Vector
+// formats get scale points per map unit.
+pub fn save(map: &Map, path: &str, region: Option<Region>, scale: f64) -> io::Result<()> {
+    let region = region
+        .or_else(|| Region::extent(map))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "nothing on the map to export"))?;
+    let (width, height) = (region.width * scale, region.height * scale);
+
+    if path.ends_with(".png") {
+        let surface = to_image(map, &region, scale)?;
+        let mut file = File::create(path)?;
+        surface.write_to_png(&mut file).map_err(cairo_error)
+    } else if path.ends_with(".svg") {
+        let surface = SvgSurface::new(width, height, Some(path)).map_err(cairo_error)?;
+        draw(map, &Context::new(&surface).map_err(cairo_error)?, &region, scale);
+        surface.finish();
+        Ok(())
+    } else if path.ends_with(".pdf") {
+        let surface = PdfSurface::new(width, height, path).map_err(cairo_error)?;
+        draw(map, &Context::new(&surface).map_err(cairo_error)?, &region, scale);
+        surface.finish();
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown image format"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network;
+
+    // Reference renderings are checked in under tests/golden. Run with
+    // UPDATE_GOLDEN=1 to write them again after an intended drawing change.
+    const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+    const GOLDEN_SCALE: f64 = 2.0;
+    // How far a color channel may be off before the pixel counts as changed
+    const CHANNEL_TOLERANCE: u8 = 24;
+    // Share of changed pixels allowed, room for antialiasing differences
+    const PIXEL_TOLERANCE: f64 = 0.002;
+
+    // Share of pixels whose color differs by more than the tolerance.
+    fn changed_pixels(actual: &mut ImageSurface, expected: &mut ImageSurface) -> Result<f64, String> {
+        let size = (actual.width(), actual.height());
+        if size != (expected.width(), expected.height()) {
+            return Err(format!("size {:?}, expected {:?}", size, (expected.width(), expected.height())));
+        }
+        let (actual_stride, expected_stride) = (actual.stride() as usize, expected.stride() as usize);
+        let actual = actual.data().map_err(|e| e.to_string())?;
+        let expected = expected.data().map_err(|e| e.to_string())?;
+
+        let mut changed = 0;
+        for y in 0..size.1 as usize {
+            for x in 0..size.0 as usize {
+                // Only the color bytes, the fourth one is alpha or unused
+                let a = &actual[y * actual_stride + x * 4..][..3];
+                let e = &expected[y * expected_stride + x * 4..][..3];
+                if a.iter().zip(e).any(|(a, e)| a.abs_diff(*e) > CHANNEL_TOLERANCE) {
+                    changed += 1;
+                }
+            }
+        }
+        Ok(changed as f64 / (size.0 * size.1) as f64)
+    }
+
+    // Render the whole map and compare with tests/golden/<name>.png. A
+    // failing rendering is kept in target/golden for a look.
+    fn assert_golden(name: &str, network: &str) {
+        let map = network::parse(network).unwrap();
+        let region = Region::extent(&map).unwrap();
+        let mut actual = to_image(&map, &region, GOLDEN_SCALE).unwrap();
+        let path = format!("{}/{}.png", GOLDEN_DIR, name);
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(GOLDEN_DIR).unwrap();
+            actual.write_to_png(&mut File::create(&path).unwrap()).unwrap();
+            return;
+        }
+
+        let mut expected = ImageSurface::create_from_png(&mut File::open(&path).expect("Missing golden image"))
+            .expect("Failed to read golden image");
+        let result = changed_pixels(&mut actual, &mut expected);
+        if !matches!(result, Ok(changed) if changed <= PIXEL_TOLERANCE) {
+            let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/target/golden");
+            std::fs::create_dir_all(dir).unwrap();
+            let failed = format!("{}/{}.png", dir, name);
+            actual.write_to_png(&mut File::create(&failed).unwrap()).unwrap();
+            panic!("Rendering of {} differs from {}: {:?}, see {}", name, path, result, failed);
+        }
+    }
+
+    #[test]
+    fn test_golden_road() {
+        // Diagonal road, lane and property offsets at an angle
+        assert_golden(
+            "road",
+            "intersection 16 16\n\
+             intersection 112 64\n\
+             road 0 1 Collector Two tile: Car Bike | Car Pedestrian\n\
+             zone 0 0 Commercial\n",
+        );
+    }
+
+    #[test]
+    fn test_golden_junction() {
+        // Turning lanes curve between the offset arms
+        assert_golden(
+            "junction",
+            "intersection 80 80\n\
+             intersection 80 16\n\
+             intersection 144 80\n\
+             intersection 80 144\n\
+             intersection 16 80\n\
+             road 0 1 Residential One tile: Car | Car\n\
+             road 0 2 Residential One tile: Car | Car\n\
+             road 0 3 Collector Two tile: Car Bike | Car Pedestrian\n\
+             road 0 4 Residential One tile: Car | Car\n",
+        );
+    }
+
+    #[test]
+    fn test_golden_roundabout() {
+        assert_golden(
+            "roundabout",
+            "intersection 80 80 roundabout 12\n\
+             intersection 80 16\n\
+             intersection 144 96\n\
+             intersection 24 112\n\
+             road 0 1 Residential One tile: Car | Car\n\
+             road 0 2 Residential One tile: Car | Car\n\
+             road 0 3 Residential One tile: Car | Car\n",
+        );
+    }
+
+    #[test]
+    fn test_opaque() {
+        let region = Region { x: 0.0, y: 0.0, width: 8.0, height: 8.0 };
+        let mut image = to_image(&Map::new(), &region, 1.0).unwrap();
+        let data = image.data().unwrap();
+        assert!(data.chunks(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn test_region() {
+        assert_eq!(
+            Region::parse("10, 20,300,200"),
+            Ok(Region { x: 10.0, y: 20.0, width: 300.0, height: 200.0 })
+        );
+        assert!(Region::parse("10,20,300").is_err());
+        assert!(Region::parse("10,20,0,200").is_err());
+        assert_eq!(Region::from_corners(5.0, 50.0, 1.0, 10.0), Region { x: 1.0, y: 10.0, width: 4.0, height: 40.0 });
+
+        assert_eq!(Region::extent(&Map::new()), None);
+        let map = network::parse("intersection 16 16\nintersection 96 16\nroad 0 1 Residential One tile: Car | Car\n").unwrap();
+        let region = Region::extent(&map).unwrap();
+        assert_eq!((region.x, region.width), (16.0 - MARGIN, 80.0 + MARGIN * 2.0));
+    }
+}
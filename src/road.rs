@@ -4,7 +4,9 @@ use std::{
 };
 
 use crate::{
+    connection::{Connection, ConnectionKind},
     curve::Curve,
+    node::Node,
     intersection::Intersection,
     lane::{Lane, LaneKind},
     property::{Property, PropertyKind}, TILE, road_profile::{RoadProfile},
@@ -12,18 +14,72 @@ use crate::{
 
 use cairo::Context;
 
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum RoadKind {
-    Normal,
+    Residential,
+    Collector,
+    Arterial,
+    Highway,
+}
+
+impl RoadKind {
+    // Default speed limit in distance per tick
+    pub fn speed_limit(&self) -> f64 {
+        match self {
+            RoadKind::Residential => 1.0,
+            RoadKind::Collector => 1.5,
+            RoadKind::Arterial => 2.0,
+            RoadKind::Highway => 3.0,
+        }
+    }
+
+    pub fn allows(&self, lane_kind: LaneKind) -> bool {
+        match self {
+            RoadKind::Residential | RoadKind::Collector | RoadKind::Arterial => true,
+            RoadKind::Highway => match lane_kind {
+                LaneKind::Car | LaneKind::Median | LaneKind::Shoulder | LaneKind::Bus => true,
+                LaneKind::Bike | LaneKind::Pedestrian | LaneKind::Parking | LaneKind::Tram => false,
+            },
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoadKind::Residential => "Residential",
+            RoadKind::Collector => "Collector",
+            RoadKind::Arterial => "Arterial",
+            RoadKind::Highway => "Highway",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RoadKind> {
+        [RoadKind::Residential, RoadKind::Collector, RoadKind::Arterial, RoadKind::Highway]
+            .iter()
+            .copied()
+            .find(|k| k.name() == name)
+    }
+
+    pub fn next(&self) -> RoadKind {
+        match self {
+            RoadKind::Residential => RoadKind::Collector,
+            RoadKind::Collector => RoadKind::Arterial,
+            RoadKind::Arterial => RoadKind::Highway,
+            RoadKind::Highway => RoadKind::Residential,
+        }
+    }
 }
 
 pub struct Road {
     pub i0: Arc<Mutex<Intersection>>,
     pub i1: Arc<Mutex<Intersection>>,
     pub i2: Arc<Mutex<Intersection>>,
+    pub kind: RoadKind,
+    pub profile: RoadProfile,
     pub curve: Curve,
     pub width: f64,
     pub properties: Vec<Property>,
     pub lanes: Vec<Arc<Mutex<Lane>>>,
+    pub connections: Vec<Arc<Mutex<Connection>>>,
 }
 
 impl Road {
@@ -32,8 +88,11 @@ impl Road {
         i1: Arc<Mutex<Intersection>>,
         i2: Arc<Mutex<Intersection>>,
         road_profile: Arc<Mutex<RoadProfile>>,
+        kind: RoadKind,
     ) -> Self {
-        let width = road_profile.lock().unwrap().width();
+        // Only keep the lanes this kind of road allows
+        let profile = road_profile.lock().unwrap().allowed(kind);
+        let width = profile.width();
 
         let mut i0_lock = i0.lock().unwrap();
         let mut i1_lock = i1.lock().unwrap();
@@ -46,31 +105,37 @@ impl Road {
         let a3 = a2 + PI / 2.0;
         
         // Define Road Central Curve
-        let n0 = i0_lock.center.offset(a0, width);
-        let n1 = i2_lock.center.offset(a2, width);
+        let n0 = i0_lock.center.offset(a0, i0_lock.arm_length(width));
+        let n1 = i2_lock.center.offset(a2, i2_lock.arm_length(width));
         let curve = Curve::new(n0, n1, a0, a2);
         
-        // Get connections from Intersections
-        let c0s = i0_lock.get_connections(a0, road_profile.clone());
-        let c1s = i2_lock.get_connections(a2, road_profile.clone());
+        // Get connections from Intersections, right lanes go from i0 to i2
+        let (c0_outs, c0_ins) = i0_lock.get_connections(
+            a0,
+            width,
+            &profile.right_lane_kinds,
+            &profile.left_lane_kinds,
+        );
+        let (c1_outs, c1_ins) = i2_lock.get_connections(
+            a2,
+            width,
+            &profile.left_lane_kinds,
+            &profile.right_lane_kinds,
+        );
 
         // Add lanes to road
         let mut lanes = Vec::new();
         let mut offset = 0.0;
-        let mut i = 0;
-        for lane_kind in &road_profile.lock().unwrap().right_lane_kinds {
-            let width = match lane_kind {
-                LaneKind::Car => 4.0,
-                LaneKind::Bike => 2.0,
-                LaneKind::Pedestrian => 2.0,
-            };
+        for (i, lane_kind) in profile.right_lane_kinds.iter().enumerate() {
+            let width = lane_kind.width();
             offset += width / 2.0;
             let l0 = Arc::new(Mutex::new(Lane::new(
-                c0s[i].clone(),
-                c1s[c1s.len() - i - 1].clone(),
+                c0_outs[i].clone(),
+                c1_ins[i].clone(),
                 curve.offset(offset),
                 width,
-                *lane_kind
+                *lane_kind,
+                kind.speed_limit(),
             )));
             offset += width / 2.0;
 
@@ -78,26 +143,21 @@ impl Road {
             lanes.push(l0.clone());
 
             // Add lanes to connections
-            c0s[i].lock().unwrap().out_lane.push(l0.clone());
-            c1s[c1s.len() - i - 1].lock().unwrap().in_lane.push(l0.clone());
-            i += 1;
+            c0_outs[i].lock().unwrap().out_lane.push(l0.clone());
+            c1_ins[i].lock().unwrap().in_lane.push(l0.clone());
         }
         
         let mut offset = 0.0;
-        let mut i = 0;
-        for lane_kind in &road_profile.lock().unwrap().left_lane_kinds {
-            let width = match lane_kind {
-                LaneKind::Car => 4.0,
-                LaneKind::Bike => 2.0,
-                LaneKind::Pedestrian => 2.0,
-            };
+        for (i, lane_kind) in profile.left_lane_kinds.iter().enumerate() {
+            let width = lane_kind.width();
             offset += width / 2.0;
             let l1 = Arc::new(Mutex::new(Lane::new(
-                c1s[i].clone(),
-                c0s[c0s.len() - i - 1].clone(),
+                c1_outs[i].clone(),
+                c0_ins[i].clone(),
                 curve.reverse().offset(offset),
                 width,
-                *lane_kind
+                *lane_kind,
+                kind.speed_limit(),
             )));
             offset += width / 2.0;
 
@@ -105,29 +165,35 @@ impl Road {
             lanes.push(l1.clone());
             
             // Add lanes to connections
-            c1s[i].lock().unwrap().out_lane.push(l1.clone());
-            c0s[c0s.len() - i - 1].lock().unwrap().in_lane.push(l1.clone());
-            i += 1;
+            c1_outs[i].lock().unwrap().out_lane.push(l1.clone());
+            c0_ins[i].lock().unwrap().in_lane.push(l1.clone());
         }
         
+        let mut connections = Vec::new();
+        connections.extend(c0_outs);
+        connections.extend(c0_ins);
+        connections.extend(c1_outs);
+        connections.extend(c1_ins);
 
         // Add Properties
         let mut properties = Vec::new();
         let length = curve.length();
         let plot_width = TILE * 4.0;
         let plot_depth = TILE * 4.0;
+        let left_width = profile.left_width();
+        let right_width = profile.right_width();
         let mut i = 0.0;
 
         while i <= length - plot_width {
             properties.push(Property::new(
                 PropertyKind::Vacant,
-                curve.n0.offset(a0, i).offset(a1, width / 2.0),
-                curve.n0.offset(a0, i).offset(a1, width / 2.0 + plot_depth),
+                curve.n0.offset(a0, i).offset(a1, left_width),
+                curve.n0.offset(a0, i).offset(a1, left_width + plot_depth),
                 curve
                     .n0
                     .offset(a0, i + plot_width)
-                    .offset(a1, width / 2.0 + plot_depth),
-                curve.n0.offset(a0, i + plot_width).offset(a1, width / 2.0),
+                    .offset(a1, left_width + plot_depth),
+                curve.n0.offset(a0, i + plot_width).offset(a1, left_width),
             ));
             i += plot_width;
         }
@@ -136,14 +202,32 @@ impl Road {
         while i <= length - plot_width {
             properties.push(Property::new(
                 PropertyKind::Vacant,
-                curve.n0.offset(a0, i).offset(a3 + PI, width / 2.0),
-                curve.n0.offset(a0, i).offset(a3 + PI, width / 2.0 + plot_depth),
-                curve.n0.offset(a0, i + plot_width).offset(a3 + PI, width / 2.0 + plot_depth),
-                curve.n0.offset(a0, i + plot_width).offset(a3 + PI, width / 2.0),
+                curve.n0.offset(a0, i).offset(a3 + PI, right_width),
+                curve.n0.offset(a0, i).offset(a3 + PI, right_width + plot_depth),
+                curve.n0.offset(a0, i + plot_width).offset(a3 + PI, right_width + plot_depth),
+                curve.n0.offset(a0, i + plot_width).offset(a3 + PI, right_width),
             ));
             i += plot_width;
         }
 
+        // Properties are reached from the closest car lane
+        for property in &mut properties {
+            let center = property.center();
+            property.access = lanes
+                .iter()
+                .filter(|l| l.lock().unwrap().kind == LaneKind::Car)
+                .map(|l| {
+                    let (d, distance) = l.lock().unwrap().closest(&center);
+                    (l.clone(), d, distance)
+                })
+                .min_by(|(_, _, d0), (_, _, d1)| d0.partial_cmp(d1).unwrap())
+                .map(|(l, d, _)| (l, d));
+        }
+
+        // Rebuild the intersection lanes now that the road is wired
+        i0_lock.add_lanes();
+        i2_lock.add_lanes();
+
         drop(i0_lock);
         drop(i1_lock);
         drop(i2_lock);
@@ -153,11 +237,56 @@ impl Road {
             i0,
             i1,
             i2,
+            kind,
+            profile,
             curve,
             width,
             properties,
             lanes,
+            connections,
+        }
+    }
+
+    // Place of each connection as (end, kind, lane), the end being 0 at i0
+    // and 1 at i2 and lanes counted from the center of the road.
+    fn connection_places(&self) -> Vec<(usize, ConnectionKind, usize)> {
+        let right = self.profile.right_lane_kinds.len();
+        let left = self.profile.left_lane_kinds.len();
+        let groups = [(0, ConnectionKind::Out, right), (0, ConnectionKind::In, left), (1, ConnectionKind::Out, left), (1, ConnectionKind::In, right)];
+        groups.iter().flat_map(|&(end, kind, count)| (0..count).map(move |lane| (end, kind, lane))).collect()
+    }
+
+    // Where the connection is on the road, stays the same when the road is
+    // built again, unlike its position in the intersection.
+    pub fn connection_place(&self, c: &Arc<Mutex<Connection>>) -> Option<(usize, ConnectionKind, usize)> {
+        let i = self.connections.iter().position(|o| Arc::ptr_eq(o, c))?;
+        self.connection_places().get(i).copied()
+    }
+
+    pub fn connection(&self, end: usize, kind: ConnectionKind, lane: usize) -> Option<Arc<Mutex<Connection>>> {
+        let i = self.connection_places().iter().position(|p| *p == (end, kind, lane))?;
+        self.connections.get(i).cloned()
+    }
+
+    // Lanes going from i0 to i2 and lanes coming back, each counted from the
+    // center of the road.
+    pub fn lanes_by_direction(&self) -> [&[Arc<Mutex<Lane>>]; 2] {
+        let (forward, backward) = self.lanes.split_at(self.profile.right_lane_kinds.len());
+        [forward, backward]
+    }
+
+    // Is n on the road?
+    pub fn contains(&self, n: &Node) -> bool {
+        let side = self.profile.left_width().max(self.profile.right_width());
+        let length = self.curve.length();
+        let mut d = 0.0;
+        while d <= length {
+            if self.curve.position_at(d).distance(n) <= side {
+                return true;
+            }
+            d += 1.0;
         }
+        false
     }
 
     pub fn draw(&self, context: &Context) {
@@ -1,6 +1,12 @@
-use crate::lane::LaneKind;
+use std::{fs, io};
 
+use cairo::Context;
+
+use crate::{lane::LaneKind, road::RoadKind};
+
+#[derive(Clone)]
 pub struct RoadProfile {
+    pub name: String,
     pub right_lane_kinds: Vec<LaneKind>,
     pub left_lane_kinds: Vec<LaneKind>,
 }
@@ -8,27 +14,183 @@ pub struct RoadProfile {
 impl RoadProfile {
     pub fn new() -> Self {
         Self {
+            name: String::new(),
             right_lane_kinds: Vec::new(),
             left_lane_kinds: Vec::new(),
         }
     }
 
+    // The profiles we start out with when there is no profiles file.
+    pub fn defaults() -> Vec<RoadProfile> {
+        vec![
+            // One tile Car only
+            RoadProfile {
+                name: String::from("One tile"),
+                right_lane_kinds: vec![LaneKind::Car],
+                left_lane_kinds: vec![LaneKind::Car],
+            },
+            // Two tile Bike Lanes and Sidewalks
+            RoadProfile {
+                name: String::from("Two tile"),
+                right_lane_kinds: vec![LaneKind::Car, LaneKind::Bike, LaneKind::Pedestrian],
+                left_lane_kinds: vec![LaneKind::Car, LaneKind::Bike, LaneKind::Pedestrian],
+            },
+            // Eight lanes
+            RoadProfile {
+                name: String::from("Eight lanes"),
+                right_lane_kinds: vec![LaneKind::Car, LaneKind::Car, LaneKind::Car, LaneKind::Car],
+                left_lane_kinds: vec![LaneKind::Car, LaneKind::Car, LaneKind::Car, LaneKind::Car],
+            },
+        ]
+    }
+
+    pub fn allowed(&self, road_kind: RoadKind) -> RoadProfile {
+        RoadProfile {
+            name: self.name.clone(),
+            right_lane_kinds: self.right_lane_kinds.iter().copied().filter(|k| road_kind.allows(*k)).collect(),
+            left_lane_kinds: self.left_lane_kinds.iter().copied().filter(|k| road_kind.allows(*k)).collect(),
+        }
+    }
+
     pub fn width(&self) -> f64 {
-        let mut width = 0.0;
-        for lane in &self.right_lane_kinds {
-            width += match lane {
-                LaneKind::Car => 4.0,
-                LaneKind::Bike => 2.0,
-                LaneKind::Pedestrian => 2.0,
-            };
+        self.right_width() + self.left_width()
+    }
+
+    pub fn right_width(&self) -> f64 {
+        self.right_lane_kinds.iter().map(|lane| lane.width()).sum()
+    }
+
+    pub fn left_width(&self) -> f64 {
+        self.left_lane_kinds.iter().map(|lane| lane.width()).sum()
+    }
+
+    // The same road seen from the other end.
+    pub fn flipped(&self) -> RoadProfile {
+        RoadProfile {
+            name: self.name.clone(),
+            right_lane_kinds: self.left_lane_kinds.clone(),
+            left_lane_kinds: self.right_lane_kinds.clone(),
         }
-        for lane in &self.left_lane_kinds {
-            width += match lane {
-                LaneKind::Car => 4.0,
-                LaneKind::Bike => 2.0,
-                LaneKind::Pedestrian => 2.0,
-            };
+    }
+
+    // Profiles are stored one per line as "name: right lanes | left lanes",
+    // lanes listed from the center of the road and outwards.
+    pub fn parse(line: &str) -> Result<RoadProfile, String> {
+        let (name, lanes) = line
+            .split_once(':')
+            .ok_or(format!("Missing ':' in profile \"{}\"", line))?;
+        let (right, left) = lanes
+            .split_once('|')
+            .ok_or(format!("Missing '|' in profile \"{}\"", line))?;
+
+        let parse_lanes = |lanes: &str| -> Result<Vec<LaneKind>, String> {
+            lanes
+                .split_whitespace()
+                .map(|name| LaneKind::from_name(name).ok_or(format!("Unknown lane kind \"{}\"", name)))
+                .collect()
+        };
+
+        Ok(RoadProfile {
+            name: name.trim().to_string(),
+            right_lane_kinds: parse_lanes(right)?,
+            left_lane_kinds: parse_lanes(left)?,
+        })
+    }
+
+    // Name without the characters that separate the parts of a line.
+    pub fn valid_name(name: &str) -> String {
+        name.chars().filter(|c| !matches!(c, ':' | '|' | '\n' | '\r')).collect()
+    }
+
+    pub fn to_line(&self) -> String {
+        let names = |lanes: &Vec<LaneKind>| {
+            lanes.iter().map(|k| k.name()).collect::<Vec<&str>>().join(" ")
+        };
+        format!("{}: {} | {}", self.name, names(&self.right_lane_kinds), names(&self.left_lane_kinds))
+    }
+
+    pub fn load_all(path: &str) -> io::Result<Vec<RoadProfile>> {
+        let mut profiles = Vec::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let profile = RoadProfile::parse(line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            profiles.push(profile);
+        }
+        Ok(profiles)
+    }
+
+    pub fn save_all(path: &str, profiles: &[&RoadProfile]) -> io::Result<()> {
+        let mut contents = String::from("# name: right lanes | left lanes\n");
+        for profile in profiles {
+            contents.push_str(&profile.to_line());
+            contents.push('\n');
         }
-        width
+        fs::write(path, contents)
+    }
+
+    // Cross-section with the left side on the left, centered on x.
+    pub fn draw_cross_section(&self, context: &Context, x: f64, y: f64, height: f64) {
+        let mut offset = x - self.width() / 2.0;
+        let lanes = self.left_lane_kinds.iter().rev().chain(self.right_lane_kinds.iter());
+        for lane in lanes {
+            let (r, g, b) = lane.info().color;
+            context.set_source_rgb(r, g, b);
+            context.rectangle(offset, y, lane.width(), height);
+            context.fill().expect("omg!");
+            offset += lane.width();
+        }
+
+        // Center line
+        let center = x - self.width() / 2.0 + self.left_width();
+        context.set_source_rgb(0.90, 0.80, 0.20);
+        context.move_to(center, y);
+        context.line_to(center, y + height);
+        context.stroke().expect("omg!");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let profile = RoadProfile::parse("Two tile: Car Bike | Car Pedestrian").unwrap();
+        assert_eq!(profile.name, "Two tile");
+        assert_eq!(profile.right_lane_kinds, vec![LaneKind::Car, LaneKind::Bike]);
+        assert_eq!(profile.left_lane_kinds, vec![LaneKind::Car, LaneKind::Pedestrian]);
+    }
+
+    #[test]
+    fn test_parse_one_side() {
+        let profile = RoadProfile::parse("One way: Car Car |").unwrap();
+        assert_eq!(profile.right_lane_kinds.len(), 2);
+        assert!(profile.left_lane_kinds.is_empty());
+    }
+
+    #[test]
+    fn test_parse_unknown_lane() {
+        assert!(RoadProfile::parse("Bad: Car Boat | Car").is_err());
+    }
+
+    #[test]
+    fn test_to_line() {
+        for profile in RoadProfile::defaults() {
+            let line = profile.to_line();
+            assert_eq!(RoadProfile::parse(&line).unwrap().to_line(), line);
+        }
+    }
+
+    #[test]
+    fn test_valid_name() {
+        let mut profile = RoadProfile::parse("Main: Car | Car").unwrap();
+        profile.name = RoadProfile::valid_name("Main St: east|west");
+        assert_eq!(profile.name, "Main St eastwest");
+        let line = profile.to_line();
+        assert_eq!(RoadProfile::parse(&line).unwrap().name, profile.name);
     }
 }
@@ -0,0 +1,135 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use crate::{agent::AgentKind, lane::Lane, map::key};
+
+// Lane in the search queue, cheapest first.
+struct Visit {
+    cost: f64,
+    lane: Arc<Mutex<Lane>>,
+}
+
+impl PartialEq for Visit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Visit {}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+// Time it takes to drive the whole lane.
+pub fn travel_time(lane: &Lane) -> f64 {
+    lane.length() / lane.max_speed().max(0.01)
+}
+
+// Lanes an agent of this kind can take after the lane.
+pub fn successors(lane: &Arc<Mutex<Lane>>, kind: AgentKind) -> Vec<Arc<Mutex<Lane>>> {
+    let c1 = lane.lock().unwrap().c1.clone();
+    let out_lanes = c1.lock().unwrap().out_lane.clone();
+    out_lanes
+        .into_iter()
+        .filter(|l| kind.can_use(l.lock().unwrap().kind))
+        .collect()
+}
+
+// Fastest way from the end of one lane to the end of another, both included.
+pub fn find_route(
+    from: &Arc<Mutex<Lane>>,
+    to: &Arc<Mutex<Lane>>,
+    kind: AgentKind,
+) -> Option<Vec<Arc<Mutex<Lane>>>> {
+    let mut costs: HashMap<usize, f64> = HashMap::new();
+    let mut previous: HashMap<usize, Arc<Mutex<Lane>>> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+
+    for lane in successors(from, kind) {
+        let cost = travel_time(&lane.lock().unwrap());
+        if cost < *costs.get(&key(&lane)).unwrap_or(&f64::MAX) {
+            costs.insert(key(&lane), cost);
+            previous.insert(key(&lane), from.clone());
+            queue.push(Visit { cost, lane });
+        }
+    }
+
+    while let Some(Visit { cost, lane }) = queue.pop() {
+        if cost > *costs.get(&key(&lane)).unwrap_or(&f64::MAX) {
+            continue;
+        }
+
+        if Arc::ptr_eq(&lane, to) {
+            // Walk back to where we came from
+            let mut route = vec![lane.clone()];
+            let mut current = lane;
+            loop {
+                let p = previous[&key(&current)].clone();
+                route.push(p.clone());
+                if Arc::ptr_eq(&p, from) {
+                    break;
+                }
+                current = p;
+            }
+            route.reverse();
+            return Some(route);
+        }
+
+        for next in successors(&lane, kind) {
+            let next_cost = cost + travel_time(&next.lock().unwrap());
+            if next_cost < *costs.get(&key(&next)).unwrap_or(&f64::MAX) {
+                costs.insert(key(&next), next_cost);
+                previous.insert(key(&next), lane.clone());
+                queue.push(Visit { cost: next_cost, lane: next });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        intersection::Intersection,
+        lane::LaneKind,
+        road::{Road, RoadKind},
+        road_profile::RoadProfile,
+    };
+
+    #[test]
+    fn test_find_route() {
+        let i0 = Arc::new(Mutex::new(Intersection::new(0.0, 0.0)));
+        let i1 = Arc::new(Mutex::new(Intersection::new(50.0, 0.0)));
+        let i2 = Arc::new(Mutex::new(Intersection::new(100.0, 0.0)));
+        let road_profile = Arc::new(Mutex::new(RoadProfile {
+            name: String::from("One tile"),
+            right_lane_kinds: vec![LaneKind::Car],
+            left_lane_kinds: vec![LaneKind::Car],
+        }));
+        let road = Road::new(i0, i1, i2, road_profile, RoadKind::Residential);
+        let there = road.lanes[0].clone();
+        let back = road.lanes[1].clone();
+
+        // Turn around at the end of the road
+        let route = find_route(&there, &back, AgentKind::Car).unwrap();
+        assert_eq!(route.len(), 3);
+        assert!(Arc::ptr_eq(&route[0], &there));
+        assert!(Arc::ptr_eq(&route[2], &back));
+
+        // Trams only run on tram lanes
+        assert!(find_route(&there, &back, AgentKind::Tram).is_none());
+    }
+}
@@ -0,0 +1,121 @@
+use cairo::Context;
+
+use crate::agent::AgentKind;
+
+pub struct TripRecord {
+    pub agent_id: usize,
+    pub kind: AgentKind,
+    pub departure: u64,
+    pub arrival: u64,
+    pub distance: f64,
+    // Ticks it would have taken on empty roads
+    pub free_flow_time: f64,
+    // Reached its destination, or just left the network at a sink
+    pub completed: bool,
+}
+
+impl TripRecord {
+    pub fn travel_time(&self) -> f64 {
+        (self.arrival - self.departure) as f64
+    }
+
+    // Time lost to traffic, signs and crossings
+    pub fn delay(&self) -> f64 {
+        (self.travel_time() - self.free_flow_time).max(0.0)
+    }
+}
+
+pub struct Statistics {
+    pub trips: Vec<TripRecord>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self { trips: Vec::new() }
+    }
+
+    pub fn record(&mut self, trip: TripRecord) {
+        self.trips.push(trip);
+    }
+
+    pub fn completed(&self) -> impl Iterator<Item = &TripRecord> {
+        self.trips.iter().filter(|t| t.completed)
+    }
+
+    pub fn completed_count(&self) -> usize {
+        self.completed().count()
+    }
+
+    fn mean(&self, value: impl Fn(&TripRecord) -> f64) -> Option<f64> {
+        let count = self.completed_count();
+        if count == 0 {
+            return None;
+        }
+        Some(self.completed().map(value).sum::<f64>() / count as f64)
+    }
+
+    pub fn mean_travel_time(&self) -> Option<f64> {
+        self.mean(|t| t.travel_time())
+    }
+
+    pub fn mean_distance(&self) -> Option<f64> {
+        self.mean(|t| t.distance)
+    }
+
+    pub fn mean_delay(&self) -> Option<f64> {
+        self.mean(|t| t.delay())
+    }
+
+    // Summary panel in the top left corner, in screen coordinates.
+    pub fn draw(&self, context: &Context, active_agents: usize) {
+        let format = |value: Option<f64>| match value {
+            Some(value) => format!("{:.1}", value),
+            None => String::from("-"),
+        };
+        let lines = [
+            format!("Agents on the road: {}", active_agents),
+            format!("Trips completed: {}", self.completed_count()),
+            format!("Trips left the map: {}", self.trips.len() - self.completed_count()),
+            format!("Mean travel time: {}", format(self.mean_travel_time())),
+            format!("Mean distance: {}", format(self.mean_distance())),
+            format!("Mean delay: {}", format(self.mean_delay())),
+        ];
+
+        context.set_source_rgba(0.10, 0.10, 0.10, 0.75);
+        context.rectangle(8.0, 8.0, 220.0, 16.0 * lines.len() as f64 + 12.0);
+        context.fill().expect("Failed to draw statistics!");
+
+        context.set_source_rgb(0.95, 0.95, 0.95);
+        context.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+        context.set_font_size(12.0);
+        for (i, line) in lines.iter().enumerate() {
+            context.move_to(16.0, 28.0 + 16.0 * i as f64);
+            context.show_text(line).expect("Failed to draw statistics!");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_means() {
+        let mut stats = Statistics::new();
+        assert_eq!(stats.mean_delay(), None);
+        for (arrival, completed) in [(100, true), (200, true), (50, false)] {
+            stats.record(TripRecord {
+                agent_id: 0,
+                kind: AgentKind::Car,
+                departure: 0,
+                arrival,
+                distance: 80.0,
+                free_flow_time: 80.0,
+                completed,
+            });
+        }
+        assert_eq!(stats.completed_count(), 2);
+        assert_eq!(stats.mean_travel_time(), Some(150.0));
+        assert_eq!(stats.mean_delay(), Some(70.0));
+    }
+}
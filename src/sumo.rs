@@ -0,0 +1,407 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    f64::consts::PI,
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    agent::AgentKind,
+    connection::Connection,
+    curve::SAMPLE_STEP,
+    demand::{Access, TICKS_PER_HOUR},
+    intersection::Intersection,
+    lane::{Lane, LaneKind},
+    map::{key, Map},
+    node::Node,
+};
+
+// Demand is exported for one day, in ticks which SUMO takes as seconds
+const DAY: u64 = TICKS_PER_HOUR * 24;
+
+fn vclass(kind: AgentKind) -> &'static str {
+    match kind {
+        AgentKind::Car => "passenger",
+        AgentKind::Truck => "truck",
+        AgentKind::Bus => "bus",
+        AgentKind::Tram => "tram",
+        AgentKind::Pedestrian => "pedestrian",
+        AgentKind::Bike => "bicycle",
+    }
+}
+
+// Who may use a lane, the same agents as in our own simulation.
+fn permissions(lane_kind: LaneKind) -> String {
+    let allowed: Vec<&str> = AgentKind::ALL
+        .iter()
+        .filter(|kind| kind.can_use(lane_kind))
+        .map(|kind| vclass(*kind))
+        .collect();
+    if allowed.is_empty() {
+        String::from("disallow=\"all\"")
+    } else {
+        format!("allow=\"{}\"", allowed.join(" "))
+    }
+}
+
+// SUMO has y pointing up.
+fn point(n: &Node) -> String {
+    format!("{:.2},{:.2}", n.x, -n.y)
+}
+
+fn shape(lanes: &[Arc<Mutex<Lane>>]) -> String {
+    let mut points = Vec::new();
+    for lane in lanes {
+        let lane = lane.lock().unwrap();
+        let length = lane.length();
+        let steps = (length / SAMPLE_STEP).ceil().max(1.0) as usize;
+        let first = if points.is_empty() { 0 } else { 1 };
+        for i in first..=steps {
+            points.push(point(&lane.position_at(length * i as f64 / steps as f64)));
+        }
+    }
+    points.join(" ")
+}
+
+// Straight, left, right or turning around, from the heading in and out.
+fn direction(a0: f64, a1: f64) -> &'static str {
+    let turn = (a1 - a0 + PI).rem_euclid(2.0 * PI) - PI;
+    if turn.abs() < PI / 6.0 {
+        "s"
+    } else if turn.abs() > PI * 5.0 / 6.0 {
+        "t"
+    } else if turn > 0.0 {
+        // Screen y points down, so clockwise
+        "r"
+    } else {
+        "l"
+    }
+}
+
+// Road lanes as SUMO lanes: (edge id, index), index 0 being the outermost.
+fn road_lanes(map: &Map) -> HashMap<usize, (String, usize)> {
+    let mut ids = HashMap::new();
+    for (r, road) in map.roads.iter().enumerate() {
+        let road = road.lock().unwrap();
+        let [forward, backward] = road.lanes_by_direction();
+        for (edge, lanes) in [(format!("R{}", r), forward), (format!("-R{}", r), backward)] {
+            for (index, lane) in lanes.iter().rev().enumerate() {
+                ids.insert(key(lane), (edge.clone(), index));
+            }
+        }
+    }
+    ids
+}
+
+// Intersection lanes from an entry to each exit it leads to, shortest first
+// found. Roundabouts take several lanes to get through.
+fn paths(start: &Arc<Mutex<Connection>>, internal: &[usize]) -> Vec<Vec<Arc<Mutex<Lane>>>> {
+    let mut paths = Vec::new();
+    let mut exits: Vec<usize> = Vec::new();
+    let mut seen: Vec<usize> = Vec::new();
+    let mut queue: VecDeque<Vec<Arc<Mutex<Lane>>>> = VecDeque::new();
+
+    let out_lanes = start.lock().unwrap().out_lane.clone();
+    for lane in out_lanes.into_iter().filter(|l| internal.contains(&key(l))) {
+        seen.push(key(&lane));
+        queue.push_back(vec![lane]);
+    }
+    while let Some(path) = queue.pop_front() {
+        let c1 = path.last().unwrap().lock().unwrap().c1.clone();
+        let out_lanes = c1.lock().unwrap().out_lane.clone();
+        if out_lanes.iter().any(|l| !internal.contains(&key(l))) {
+            // Onto a road
+            if !exits.contains(&key(&c1)) {
+                exits.push(key(&c1));
+                paths.push(path.clone());
+            }
+            continue;
+        }
+        for lane in out_lanes {
+            if !seen.contains(&key(&lane)) {
+                seen.push(key(&lane));
+                let mut next = path.clone();
+                next.push(lane);
+                queue.push_back(next);
+            }
+        }
+    }
+    paths
+}
+
+// Junction logic is left out, `netconvert --sumo-net-file` adds the right of way.
+pub fn to_net_xml(map: &Map) -> String {
+    let ids = road_lanes(map);
+    let index_of = |intersection: &Arc<Mutex<Intersection>>| map.intersections.iter().position(|i| Arc::ptr_eq(i, intersection));
+
+    let mut edges = String::new();
+    let mut bounds = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for (r, road) in map.roads.iter().enumerate() {
+        let road = road.lock().unwrap();
+        let (j0, j2) = match (index_of(&road.i0), index_of(&road.i2)) {
+            (Some(j0), Some(j2)) => (j0, j2),
+            _ => continue,
+        };
+        for n in [road.curve.n0, road.curve.n1].iter() {
+            bounds = (bounds.0.min(n.x), bounds.1.min(-n.y), bounds.2.max(n.x), bounds.3.max(-n.y));
+        }
+
+        for (edge, from, to) in [(format!("R{}", r), j0, j2), (format!("-R{}", r), j2, j0)] {
+            let mut lanes: Vec<(usize, &Arc<Mutex<Lane>>)> = road
+                .lanes
+                .iter()
+                .filter_map(|l| ids.get(&key(l)).filter(|(e, _)| *e == edge).map(|(_, i)| (*i, l)))
+                .collect();
+            if lanes.is_empty() {
+                continue;
+            }
+            lanes.sort_by_key(|(i, _)| *i);
+            edges.push_str(&format!("    <edge id=\"{}\" from=\"J{}\" to=\"J{}\" priority=\"-1\">\n", edge, from, to));
+            for (index, lane) in lanes {
+                let shape = shape(std::slice::from_ref(lane));
+                let lane = lane.lock().unwrap();
+                edges.push_str(&format!(
+                    "        <lane id=\"{}_{}\" index=\"{}\" {} speed=\"{:.2}\" length=\"{:.2}\" width=\"{:.2}\" shape=\"{}\"/>\n",
+                    edge,
+                    index,
+                    index,
+                    permissions(lane.kind),
+                    lane.speed_limit,
+                    lane.length(),
+                    lane.width,
+                    shape
+                ));
+            }
+            edges.push_str("    </edge>\n");
+        }
+    }
+
+    let mut internal_edges = String::new();
+    let mut junctions = String::new();
+    let mut connections = String::new();
+    for (j, intersection) in map.intersections.iter().enumerate() {
+        let intersection = intersection.lock().unwrap();
+        let internal: Vec<usize> = intersection.lanes.iter().map(key).collect();
+
+        let mut incoming = Vec::new();
+        let mut internal_lanes = Vec::new();
+        for c0 in &intersection.connections {
+            let in_lanes: Vec<(String, usize)> = c0
+                .lock()
+                .unwrap()
+                .in_lane
+                .iter()
+                .filter_map(|l| ids.get(&key(l)).cloned())
+                .collect();
+            if in_lanes.is_empty() {
+                continue;
+            }
+            incoming.extend(in_lanes.iter().map(|(edge, index)| format!("{}_{}", edge, index)));
+
+            for path in paths(c0, &internal) {
+                let c1 = path.last().unwrap().lock().unwrap().c1.clone();
+                let out_lanes: Vec<Arc<Mutex<Lane>>> = c1.lock().unwrap().out_lane.clone();
+                let a0 = {
+                    let first = path[0].lock().unwrap();
+                    first.heading_at(0.0)
+                };
+                let a1 = {
+                    let last = path.last().unwrap().lock().unwrap();
+                    last.heading_at(last.length())
+                };
+                let dir = direction(a0, a1);
+                let length: f64 = path.iter().map(|l| l.lock().unwrap().length()).sum();
+                let speed = path.iter().map(|l| l.lock().unwrap().speed_limit).fold(f64::MAX, f64::min);
+                let lane_kind = path[0].lock().unwrap().kind;
+
+                let id = format!(":J{}_{}", j, internal_lanes.len());
+                internal_lanes.push(format!("{}_0", id));
+                internal_edges.push_str(&format!(
+                    "    <edge id=\"{}\" function=\"internal\">\n        <lane id=\"{}_0\" index=\"0\" {} speed=\"{:.2}\" length=\"{:.2}\" shape=\"{}\"/>\n    </edge>\n",
+                    id,
+                    id,
+                    permissions(lane_kind),
+                    speed,
+                    length,
+                    shape(&path)
+                ));
+
+                for out_lane in &out_lanes {
+                    let (to_edge, to_index) = match ids.get(&key(out_lane)) {
+                        Some(to) => to,
+                        None => continue,
+                    };
+                    for (from_edge, from_index) in &in_lanes {
+                        connections.push_str(&format!(
+                            "    <connection from=\"{}\" to=\"{}\" fromLane=\"{}\" toLane=\"{}\" via=\"{}_0\" dir=\"{}\" state=\"M\"/>\n",
+                            from_edge, to_edge, from_index, to_index, id, dir
+                        ));
+                    }
+                    connections.push_str(&format!(
+                        "    <connection from=\"{}\" to=\"{}\" fromLane=\"0\" toLane=\"{}\" dir=\"{}\" state=\"M\"/>\n",
+                        id, to_edge, to_index, dir
+                    ));
+                }
+            }
+        }
+
+        let kind = if internal_lanes.is_empty() { "dead_end" } else { "unregulated" };
+        junctions.push_str(&format!(
+            "    <junction id=\"J{}\" type=\"{}\" x=\"{:.2}\" y=\"{:.2}\" incLanes=\"{}\" intLanes=\"{}\" shape=\"\"/>\n",
+            j,
+            kind,
+            intersection.center.x,
+            -intersection.center.y,
+            incoming.join(" "),
+            internal_lanes.join(" ")
+        ));
+    }
+
+    if bounds.0 > bounds.2 {
+        bounds = (0.0, 0.0, 0.0, 0.0);
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <net version=\"1.16\" junctionCornerDetail=\"5\" limitTurnSpeed=\"5.50\">\n\
+         \x20   <location netOffset=\"0.00,0.00\" convBoundary=\"{:.2},{:.2},{:.2},{:.2}\" \
+         origBoundary=\"-10000000000.00,-10000000000.00,10000000000.00,10000000000.00\" projParameter=\"!\"/>\n\
+         {}{}{}{}</net>\n",
+        bounds.0, bounds.1, bounds.2, bounds.3, internal_edges, edges, junctions, connections
+    )
+}
+
+// Trips of one day: flows for the OD matrix and for the commutes between
+// zoned properties, at the hourly rates of our own demand model.
+pub fn to_rou_xml(map: &Map) -> String {
+    let ids = road_lanes(map);
+    let mut text = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<routes>\n");
+    for kind in AgentKind::ALL.iter() {
+        let max_speed = if kind.top_speed() < f64::MAX {
+            format!(" maxSpeed=\"{:.2}\"", kind.top_speed())
+        } else {
+            String::new()
+        };
+        text.push_str(&format!(
+            "    <vType id=\"{}\" vClass=\"{}\" length=\"{:.2}\" width=\"{:.2}\" accel=\"{:.2}\" minGap=\"1.50\"{}/>\n",
+            kind.name(),
+            vclass(*kind),
+            kind.length(),
+            kind.width(),
+            kind.acceleration(),
+            max_speed
+        ));
+    }
+
+    // As (edge, lane index, position)
+    let place = |access: &Access| -> Option<(String, usize, f64)> {
+        let (edge, index) = ids.get(&key(&access.0))?;
+        Some((edge.clone(), *index, access.1))
+    };
+    let trip = |from: &Access, to: &Access| -> Option<String> {
+        let ((from_edge, from_lane, from_pos), (to_edge, _, to_pos)) = (place(from)?, place(to)?);
+        Some(format!(
+            "from=\"{}\" to=\"{}\" departLane=\"{}\" departPos=\"{:.2}\" arrivalPos=\"{:.2}\"",
+            from_edge, to_edge, from_lane, from_pos, to_pos
+        ))
+    };
+
+    if let Some(od) = &map.od {
+        for (p, pair) in od.pairs.iter().enumerate() {
+            let (origins, _) = map.od_endpoints(&pair.origin);
+            let (_, destinations) = map.od_endpoints(&pair.destination);
+            // Spread evenly over all the ways from the origin to the destination
+            let share = pair.trips_per_hour / (origins.len() * destinations.len()).max(1) as f64;
+            for (o, from) in origins.iter().enumerate() {
+                for (d, to) in destinations.iter().enumerate() {
+                    if let Some(trip) = trip(from, to) {
+                        text.push_str(&format!(
+                            "    <flow id=\"od{}_{}_{}\" type=\"Car\" begin=\"0\" end=\"{}\" vehsPerHour=\"{:.4}\" {}/>\n",
+                            p, o, d, DAY, share, trip
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    // Commutes for every hour, spread evenly over the work places like the
+    // simulation picks them
+    let (homes, work) = map.commute_endpoints();
+    if map.demand.enabled && !work.is_empty() {
+        for hour in 0..24 {
+            let (to_work, to_home) = map.demand.per_hour(hour);
+            let (to_work, to_home) = (to_work / work.len() as f64, to_home / work.len() as f64);
+            let (begin, end) = (hour as u64 * TICKS_PER_HOUR, (hour as u64 + 1) * TICKS_PER_HOUR);
+            for (h, home) in homes.iter().enumerate() {
+                for (w, place) in work.iter().enumerate() {
+                    for (id, from, to, rate) in [("w", home, place, to_work), ("h", place, home, to_home)] {
+                        if rate <= 0.0 {
+                            continue;
+                        }
+                        if let Some(trip) = trip(from, to) {
+                            text.push_str(&format!(
+                                "    <flow id=\"{}{}_{}_{}\" type=\"Car\" begin=\"{}\" end=\"{}\" vehsPerHour=\"{:.4}\" {}/>\n",
+                                id, hour, h, w, begin, end, rate, trip
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    text.push_str("</routes>\n");
+    text
+}
+
+// Writes the network to path and the demand next to it, as .rou.xml.
+pub fn save(map: &Map, path: &str) -> io::Result<()> {
+    fs::write(path, to_net_xml(map))?;
+    let routes = format!("{}.rou.xml", path.trim_end_matches(".net.xml"));
+    fs::write(routes, to_rou_xml(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{network, od::OdMatrix};
+
+    #[test]
+    fn test_sumo() {
+        let mut map = network::parse(
+            "intersection 0 0\n\
+             intersection 96 0\n\
+             intersection 96 96\n\
+             road 0 1 Residential One tile: Car | Car\n\
+             road 1 2 Residential Bike one way: Car Bike | Car\n\
+             zone 0 0 Residential\n\
+             zone 1 0 Commercial\n",
+        )
+        .unwrap();
+        map.od = Some(OdMatrix::parse("I0,I2,60").unwrap());
+
+        let net = to_net_xml(&map);
+        assert_eq!(net.matches("<edge ").count() - net.matches("function=\"internal\"").count(), 4);
+        assert_eq!(net.matches("<junction ").count(), 3);
+        assert!(net.contains("<lane id=\"R1_0\" index=\"0\" allow=\"bicycle\""));
+        assert!(net.contains("<lane id=\"R1_1\" index=\"1\" allow=\"passenger truck bus bicycle\""));
+        // Going straight through the middle intersection is a right turn
+        assert!(net.contains("from=\"R0\" to=\"R1\" fromLane=\"0\" toLane=\"1\" via=\":J1_"));
+        assert!(net.contains("dir=\"r\""));
+
+        let routes = to_rou_xml(&map);
+        assert_eq!(routes.matches("<vType ").count(), AgentKind::ALL.len());
+        assert!(routes.contains("<flow id=\"od0_0_0\" type=\"Car\" begin=\"0\" end=\"86400\" vehsPerHour=\"60.0000\" from=\"R0\" to=\"R1\""));
+        // A quarter of the two trips a day leave for work between 7 and 8
+        assert!(routes.contains("<flow id=\"w7_0_0\" type=\"Car\" begin=\"25200\" end=\"28800\" vehsPerHour=\"0.5000\""));
+        assert!(!routes.contains("<trip "));
+        assert_eq!(to_rou_xml(&map), routes);
+    }
+
+    #[test]
+    fn test_direction() {
+        assert_eq!(direction(0.0, 0.1), "s");
+        assert_eq!(direction(0.0, PI / 2.0), "r");
+        assert_eq!(direction(PI, PI / 2.0), "l");
+        assert_eq!(direction(0.0, PI), "t");
+    }
+}
@@ -2,20 +2,69 @@ use std::{rc::Rc, cell::RefCell, sync::{Arc, Mutex}};
 
 use cairo::Context;
 
-use crate::intersection::Intersection;
+use crate::{intersection::Intersection, node::Node, overlay::Overlay, property::PropertyKind, road::RoadKind, transit::{Stop, draw_stop}, SCALE};
+
+// Pixels or points per map unit exported images can have
+const EXPORT_SCALES: [f64; 4] = [SCALE, 6.0, 12.0, 1.0];
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Tool {
+    Road,
+    Sign,
+    Flip,
+    Transit,
+    Zone,
+    Sink,
+    Region,
+}
 
 pub struct Toolbar {
     // Alternatives
+    pub tool: Tool,
+    pub road_kind: RoadKind,
+    pub profile: usize,
+    pub zone: PropertyKind,
+    pub show_stats: bool,
+    // Lanes colored by congestion
+    pub overlay: Overlay,
     // State
     pub selected: Option<Arc<Mutex<Intersection>>>,
+    // Stops of the transit line being laid out
+    pub stops: Vec<Stop>,
+    // First corner of the region to export
+    pub region_start: Option<Node>,
+    pub export_scale: f64,
 }
 
 impl Toolbar {
     pub fn new() -> Self {
-        Self { selected: None}
+        Self { tool: Tool::Road, road_kind: RoadKind::Residential, profile: 0, zone: PropertyKind::Residential, show_stats: false, overlay: Overlay::None, selected: None, stops: Vec::new(), region_start: None, export_scale: SCALE}
+    }
+
+    pub fn set_tool(&mut self, tool: Tool) {
+        self.tool = tool;
+        self.selected = None;
+        self.stops.clear();
+        self.region_start = None;
+    }
+
+    pub fn next_export_scale(&mut self) {
+        let i = EXPORT_SCALES.iter().position(|s| *s == self.export_scale).unwrap_or(0);
+        self.export_scale = EXPORT_SCALES[(i + 1) % EXPORT_SCALES.len()];
     }
 
     pub fn draw(&self, context: &Context) {
+        for stop in &self.stops {
+            draw_stop(context, stop, (0.2, 0.2, 0.2));
+        }
 
+        if let Some(n) = &self.region_start {
+            context.set_source_rgb(0.9, 0.9, 0.9);
+            context.move_to(n.x - 3.0, n.y);
+            context.line_to(n.x + 3.0, n.y);
+            context.move_to(n.x, n.y - 3.0);
+            context.line_to(n.x, n.y + 3.0);
+            context.stroke().expect("Failed to draw region corner!");
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,98 @@
+use std::{
+    f64::consts::PI,
+    sync::{Arc, Mutex},
+};
+
+use cairo::Context;
+
+use crate::{agent::AgentKind, lane::Lane, route::find_route};
+
+#[derive(Clone)]
+pub struct Stop {
+    pub lane: Arc<Mutex<Lane>>,
+    pub distance: f64,
+}
+
+pub struct TransitLine {
+    pub name: String,
+    pub color: (f64, f64, f64),
+    pub kind: AgentKind,
+    pub stops: Vec<Stop>,
+    // Lanes from the first to the last stop
+    pub path: Vec<Arc<Mutex<Lane>>>,
+    // Ticks between departures
+    pub headway: u64,
+    // Ticks spent at each stop
+    pub dwell: u32,
+    pub last_departure: Option<u64>,
+}
+
+impl TransitLine {
+    pub fn new(
+        name: String,
+        color: (f64, f64, f64),
+        kind: AgentKind,
+        stops: Vec<Stop>,
+        headway: u64,
+        dwell: u32,
+    ) -> Result<Self, String> {
+        if stops.len() < 2 {
+            return Err(format!("{} needs at least two stops", name));
+        }
+
+        let mut path = vec![stops[0].lane.clone()];
+        for pair in stops.windows(2) {
+            let (from, to) = (&pair[0], &pair[1]);
+            if Arc::ptr_eq(&from.lane, &to.lane) && from.distance <= to.distance {
+                // Further down the same lane
+                continue;
+            }
+            if !kind.can_use(to.lane.lock().unwrap().kind) {
+                return Err(format!("{} can not stop on that kind of lane", name));
+            }
+            match find_route(&from.lane, &to.lane, kind) {
+                Some(route) => path.extend(route.into_iter().skip(1)),
+                None => return Err(format!("{} has no way between two of its stops", name)),
+            }
+        }
+
+        Ok(Self {
+            name,
+            color,
+            kind,
+            stops,
+            path,
+            headway,
+            dwell,
+            last_departure: None,
+        })
+    }
+
+    pub fn is_due(&self, tick: u64) -> bool {
+        match self.last_departure {
+            Some(last) => tick >= last + self.headway,
+            None => true,
+        }
+    }
+
+    pub fn draw(&self, context: &Context) {
+        let (r, g, b) = self.color;
+        context.set_source_rgba(r, g, b, 0.6);
+        for lane in &self.path {
+            lane.lock().unwrap().curve.plot(context);
+            context.stroke().expect("Failed to draw transit line!");
+        }
+        for stop in &self.stops {
+            draw_stop(context, stop, self.color);
+        }
+    }
+}
+
+pub fn draw_stop(context: &Context, stop: &Stop, (r, g, b): (f64, f64, f64)) {
+    let n = stop.lane.lock().unwrap().position_at(stop.distance);
+    context.set_source_rgb(0.95, 0.95, 0.95);
+    context.arc(n.x, n.y, 1.5, 0.0, PI * 2.0);
+    context.fill_preserve().expect("Failed to draw stop!");
+    context.set_source_rgb(r, g, b);
+    context.stroke().expect("Failed to draw stop!");
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use cairo::Context;
+
+use crate::{connection::Connection, lane::{Lane, LaneKind}, map::{key, Map}, node::Node, TILE};
+
+// Intersections closer than this are on top of each other
+const MIN_INTERSECTION_DISTANCE: f64 = TILE;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ProblemKind {
+    NoSuccessors,
+    MismatchedLaneKind,
+    Disconnected,
+    ZeroLength,
+    DegenerateArc,
+    OverlappingIntersections,
+}
+
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub message: String,
+    // Where to look
+    pub at: Node,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} {:.1}: {}", self.at.x, self.at.y, self.message)
+    }
+}
+
+impl Problem {
+    pub fn draw(&self, context: &Context) {
+        context.set_source_rgba(0.90, 0.10, 0.10, 0.8);
+        context.arc(self.at.x, self.at.y, 3.0, 0.0, PI * 2.0);
+        context.stroke().expect("Failed to draw problem!");
+    }
+}
+
+// Every lane on the map, road lanes first.
+pub fn all_lanes(map: &Map) -> Vec<Arc<Mutex<Lane>>> {
+    let mut lanes = Vec::new();
+    for road in &map.roads {
+        lanes.extend(road.lock().unwrap().lanes.iter().cloned());
+    }
+    for intersection in &map.intersections {
+        lanes.extend(intersection.lock().unwrap().lanes.iter().cloned());
+    }
+    lanes
+}
+
+// Groups of connections joined by lanes, ignoring their direction.
+fn components(lanes: &[Arc<Mutex<Lane>>]) -> Vec<Vec<Arc<Mutex<Connection>>>> {
+    let mut parent: HashMap<usize, usize> = HashMap::new();
+    let mut connections: HashMap<usize, Arc<Mutex<Connection>>> = HashMap::new();
+
+    fn find(parent: &mut HashMap<usize, usize>, k: usize) -> usize {
+        let p = *parent.entry(k).or_insert(k);
+        if p == k {
+            return k;
+        }
+        let root = find(parent, p);
+        parent.insert(k, root);
+        root
+    }
+
+    for lane in lanes {
+        let lane = lane.lock().unwrap();
+        let (k0, k1) = (key(&lane.c0), key(&lane.c1));
+        connections.insert(k0, lane.c0.clone());
+        connections.insert(k1, lane.c1.clone());
+        let (r0, r1) = (find(&mut parent, k0), find(&mut parent, k1));
+        if r0 != r1 {
+            parent.insert(r0, r1);
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<Arc<Mutex<Connection>>>> = HashMap::new();
+    let mut keys: Vec<usize> = connections.keys().copied().collect();
+    keys.sort();
+    for k in keys {
+        let root = find(&mut parent, k);
+        groups.entry(root).or_default().push(connections[&k].clone());
+    }
+    let mut groups: Vec<Vec<Arc<Mutex<Connection>>>> = groups.into_values().collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.len()));
+    groups
+}
+
+pub fn validate(map: &Map) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let road_lanes: Vec<usize> = map
+        .roads
+        .iter()
+        .flat_map(|road| road.lock().unwrap().lanes.iter().map(key).collect::<Vec<usize>>())
+        .collect();
+    let lanes = all_lanes(map);
+
+    for l in &lanes {
+        let is_road_lane = road_lanes.contains(&key(l));
+        let lane = l.lock().unwrap();
+        let length = lane.length();
+        let end = lane.position_at(length);
+        let c0 = lane.c0.lock().unwrap();
+        let c1 = lane.c1.lock().unwrap();
+
+        if lane.kind.is_traversable() && c1.out_lane.is_empty() && !c1.sink {
+            problems.push(Problem {
+                kind: ProblemKind::NoSuccessors,
+                message: format!("{} lane leads nowhere", lane.kind.name()),
+                at: end,
+            });
+        }
+
+        // Road lanes join their own kind, lanes in intersections join at least one
+        let mismatched = if is_road_lane {
+            c0.lane_kind != lane.kind || c1.lane_kind != lane.kind
+        } else {
+            c0.lane_kind != lane.kind && c1.lane_kind != lane.kind
+        };
+        if mismatched {
+            problems.push(Problem {
+                kind: ProblemKind::MismatchedLaneKind,
+                message: format!(
+                    "{} lane joins {} to {}",
+                    lane.kind.name(),
+                    c0.lane_kind.name(),
+                    c1.lane_kind.name()
+                ),
+                at: c0.center,
+            });
+        }
+
+        if length.is_nan() || length <= 0.01 {
+            problems.push(Problem {
+                kind: ProblemKind::ZeroLength,
+                message: format!("{} lane has no length", lane.kind.name()),
+                at: c0.center,
+            });
+        }
+
+        if lane.curve.is_curved {
+            let r0 = lane.curve.c.distance(&lane.curve.n0);
+            let r1 = lane.curve.c.distance(&lane.curve.n1);
+            if r0.is_nan() || r1.is_nan() || r0 <= 0.5 || (r0 - r1).abs() >= 0.5 {
+                problems.push(Problem {
+                    kind: ProblemKind::DegenerateArc,
+                    message: format!("{} lane arc has radius {:.1} to {:.1}", lane.kind.name(), r0, r1),
+                    at: c0.center,
+                });
+            }
+        }
+    }
+
+    // Each kind of lane makes its own network, lanes nobody uses don't need one
+    for kind in LaneKind::ALL {
+        if !kind.is_traversable() {
+            continue;
+        }
+        let kind_lanes: Vec<Arc<Mutex<Lane>>> = lanes
+            .iter()
+            .filter(|l| l.lock().unwrap().kind == kind)
+            .cloned()
+            .collect();
+        for group in components(&kind_lanes).iter().skip(1) {
+            problems.push(Problem {
+                kind: ProblemKind::Disconnected,
+                message: format!("{} {} connections are cut off from the rest", group.len(), kind.name()),
+                at: group[0].lock().unwrap().center,
+            });
+        }
+    }
+
+    for (i, i0) in map.intersections.iter().enumerate() {
+        for i1 in map.intersections.iter().skip(i + 1) {
+            let n0 = i0.lock().unwrap().center;
+            let n1 = i1.lock().unwrap().center;
+            if n0.distance(&n1) < MIN_INTERSECTION_DISTANCE {
+                problems.push(Problem {
+                    kind: ProblemKind::OverlappingIntersections,
+                    message: format!("intersections {:.1} apart", n0.distance(&n1)),
+                    at: n0,
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network;
+
+    #[test]
+    fn test_validate() {
+        let map = network::parse(
+            "intersection 0 0\n\
+             intersection 96 0\n\
+             intersection 0 96\n\
+             intersection 96 96\n\
+             intersection 100 96\n\
+             road 0 1 Residential Two tile: Car Bike Pedestrian | Car Bike Pedestrian\n\
+             road 2 3 Residential One way: Car |\n",
+        )
+        .unwrap();
+        let problems = validate(&map);
+        let count = |kind| problems.iter().filter(|p| p.kind == kind).count();
+
+        // The one way road ends nowhere and is not connected to the other road
+        assert_eq!(count(ProblemKind::NoSuccessors), 1);
+        assert_eq!(count(ProblemKind::Disconnected), 1);
+        assert_eq!(count(ProblemKind::OverlappingIntersections), 1);
+        assert_eq!(count(ProblemKind::MismatchedLaneKind), 0);
+        assert_eq!(count(ProblemKind::DegenerateArc), 0);
+    }
+
+    #[test]
+    fn test_validate_median() {
+        let map = network::parse(
+            "intersection 0 0\n\
+             intersection 96 0\n\
+             intersection 192 0\n\
+             road 0 1 Residential Boulevard: Car Median | Car Median\n\
+             road 1 2 Residential Boulevard: Car Median | Car Median\n",
+        )
+        .unwrap();
+        // Medians end at each intersection and that's fine
+        let problems = validate(&map);
+        assert!(problems.iter().all(|p| p.kind != ProblemKind::Disconnected));
+    }
+}